@@ -0,0 +1,187 @@
+use embedded_hal_async::digital::Wait;
+
+use crate::interface::RegisterInterface;
+use crate::low_level::{self, SwFlow};
+use crate::{Error, FlowThresholds, Sc16is752, StreamProfile, SwFlowMode, TriggerLevels};
+
+/// Configuration changes collected by [`Sc16is752::batch`].
+///
+/// Nothing is written until [`Batch::commit`], which groups all accesses to
+/// the enhanced register set so that LCR is switched to 0xBF (and back) only
+/// once, then writes TCR and finally the general registers.
+#[must_use = "a batch does nothing until committed"]
+pub struct Batch<'a, Spi, Irq, const BUF: usize> {
+    uart: &'a mut Sc16is752<Spi, Irq, BUF>,
+    sw_flow: Option<(u8, u8, SwFlowMode)>,
+    sw_flow_off: bool,
+    hw_flow: Option<(bool, bool)>,
+    thresholds: Option<FlowThresholds>,
+    triggers: Option<TriggerLevels>,
+    profile: Option<StreamProfile>,
+}
+
+impl<'a, Spi, Irq, const BUF: usize> Batch<'a, Spi, Irq, BUF>
+where
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    pub(crate) fn new(uart: &'a mut Sc16is752<Spi, Irq, BUF>) -> Self {
+        Batch {
+            uart,
+            sw_flow: None,
+            sw_flow_off: false,
+            hw_flow: None,
+            thresholds: None,
+            triggers: None,
+            profile: None,
+        }
+    }
+
+    /// See [`Sc16is752::enable_software_flow_control`].
+    pub fn software_flow_control(mut self, xon: u8, xoff: u8, mode: SwFlowMode) -> Self {
+        self.sw_flow = Some((xon, xoff, mode));
+        self.sw_flow_off = false;
+        self
+    }
+
+    /// Turns Xon/Xoff flow control off in both directions.
+    pub fn disable_software_flow_control(mut self) -> Self {
+        self.sw_flow = None;
+        self.sw_flow_off = true;
+        self
+    }
+
+    /// Enables or disables automatic RTS and CTS flow control.
+    pub fn hardware_flow_control(mut self, auto_rts: bool, auto_cts: bool) -> Self {
+        self.hw_flow = Some((auto_rts, auto_cts));
+        self
+    }
+
+    /// Programs the TCR halt/resume levels used by both kinds of flow control.
+    pub fn flow_thresholds(mut self, thresholds: FlowThresholds) -> Self {
+        self.thresholds = Some(thresholds);
+        self
+    }
+
+    /// See [`Sc16is752::set_trigger_levels`].
+    pub fn trigger_levels(mut self, levels: TriggerLevels) -> Self {
+        self.triggers = Some(levels);
+        self
+    }
+
+    /// See [`Sc16is752::set_streaming_profile`].
+    pub fn streaming_profile(mut self, profile: StreamProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub async fn commit(self) -> Result<(), Error<Spi::Error>> {
+        let Batch {
+            uart,
+            sw_flow,
+            sw_flow_off,
+            hw_flow,
+            thresholds,
+            triggers,
+            profile,
+        } = self;
+        let regs = &mut uart.regs;
+        let channel = uart.channel;
+
+        let tcr = thresholds.map(FlowThresholds::to_tcr).transpose()?;
+        let tlr = triggers.map(TriggerLevels::to_tlr).transpose()?;
+
+        let enhanced = sw_flow.is_some() || sw_flow_off || hw_flow.is_some();
+        if enhanced || tcr.is_some() || tlr.is_some() {
+            regs.with_enhanced(channel, async |regs| {
+                // TCR and TLR are only accessible with enhanced functions enabled
+                let mut efr = regs.read_efr(channel).await?.with_enhanced_functions(true);
+
+                if let Some((xon, xoff, mode)) = sw_flow {
+                    regs.write(low_level::XON1, channel, [xon]).await?;
+                    regs.write(low_level::XOFF1, channel, [xoff]).await?;
+
+                    let (rx, tx) = mode.efr_bits();
+                    efr = efr.with_rx_sw_flow(rx).with_tx_sw_flow(tx);
+                }
+
+                if sw_flow_off {
+                    efr = efr
+                        .with_rx_sw_flow(SwFlow::None)
+                        .with_tx_sw_flow(SwFlow::None);
+                }
+
+                if let Some((auto_rts, auto_cts)) = hw_flow {
+                    efr = efr.with_auto_rts(auto_rts).with_auto_cts(auto_cts);
+                }
+
+                regs.write_efr(channel, efr).await
+            })
+            .await?;
+        }
+
+        if let Some(tcr) = tcr {
+            regs.write_tcr(channel, tcr).await?;
+        }
+        if let Some(tlr) = tlr {
+            regs.write_tlr(channel, tlr).await?;
+        }
+
+        if let Some(profile) = profile {
+            let (fcr, ier) = profile.registers();
+            uart.write_fcr(fcr).await?;
+            uart.regs.write_ier(channel, ier).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Channel;
+    use crate::low_level::{EFR, FCR, IER, LCR, MCR, TCR, TLR};
+    use crate::mock::{MockIrq, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_flow_control_enters_enhanced_once() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+
+        block_on(
+            uart.batch()
+                .software_flow_control(0x11, 0x13, SwFlowMode::Both)
+                .hardware_flow_control(true, true)
+                .flow_thresholds(FlowThresholds {
+                    halt: 56,
+                    resume: 8,
+                })
+                .streaming_profile(StreamProfile::HighThroughput)
+                .commit(),
+        )
+        .unwrap();
+
+        assert_eq!(spi.writes(Channel::A, Window::General, LCR), [0xBF, 0x03]);
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, EFR), [0xDA]);
+        assert_eq!(spi.writes(Channel::A, Window::Tcr, TCR), [0x2E]);
+        assert_eq!(spi.writes(Channel::A, Window::General, FCR), [0xB1]);
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x01]);
+    }
+
+    #[test]
+    fn test_trigger_levels() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        block_on(uart.set_trigger_levels(TriggerLevels { rx: 20, tx: 61 }))
+            .expect_err("61 is above 60");
+        assert_eq!(spi.transactions(), 0);
+
+        block_on(uart.set_trigger_levels(TriggerLevels { rx: 23, tx: 4 })).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::Enhanced, EFR), 0x10);
+        assert_eq!(spi.writes(Channel::B, Window::Tcr, TLR), [0x51]);
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x00);
+    }
+}
@@ -0,0 +1,308 @@
+//! The driver for projects without an async executor, over the blocking
+//! `embedded-hal` traits.
+//!
+//! [`Blocking`] presents a blocking SPI device, I2C bus or input pin as its
+//! async counterpart, whose futures then complete on the first poll, so that
+//! [`BlockingSc16is752`] can run the async driver to completion in place.
+//! Everything but the bus and pin traits (registers, [`Config`](crate::Config),
+//! errors) is shared with the async driver.
+//!
+//! With the `nb` feature, [`BlockingSc16is752`] also implements the
+//! `embedded-hal-nb` serial traits, one byte at a time out of the FIFOs.
+
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use embedded_hal::digital::InputPin;
+use embedded_hal::{i2c, spi};
+use embedded_io::{ErrorType, Read, Write};
+
+use crate::interface::RegisterInterface;
+use crate::{Channel, Error, Sc16is752};
+
+/// Wraps a blocking `embedded-hal` SPI device, I2C bus or input pin to
+/// implement the corresponding `embedded-hal-async` trait. An IRQ pin is
+/// polled, so waiting for it spins.
+pub struct Blocking<T>(pub T);
+
+impl<T: spi::ErrorType> spi::ErrorType for Blocking<T> {
+    type Error = T::Error;
+}
+
+impl<T: spi::SpiDevice> embedded_hal_async::spi::SpiDevice for Blocking<T> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [spi::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.0.transaction(operations)
+    }
+}
+
+impl<T: i2c::ErrorType> i2c::ErrorType for Blocking<T> {
+    type Error = T::Error;
+}
+
+impl<T: i2c::I2c> embedded_hal_async::i2c::I2c for Blocking<T> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.0.transaction(address, operations)
+    }
+}
+
+impl<T: embedded_hal::digital::ErrorType> embedded_hal::digital::ErrorType for Blocking<T> {
+    type Error = T::Error;
+}
+
+impl<T: InputPin> Blocking<T> {
+    async fn wait_for(&mut self, low: bool) -> Result<(), T::Error> {
+        while self.0.is_low()? != low {
+            crate::yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+impl<T: InputPin> embedded_hal_async::digital::Wait for Blocking<T> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await?;
+        self.wait_for(false).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await?;
+        self.wait_for(true).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        let low = self.0.is_low()?;
+        self.wait_for(!low).await
+    }
+}
+
+/// Polls `fut` until it completes. With the [`Blocking`] adapters nothing
+/// ever needs waking, so this simply spins.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// A channel driven through blocking SPI (or I2C, with
+/// [`I2cInterface`](crate::I2cInterface) inside [`Blocking`]) and a polled
+/// IRQ pin, implementing [`embedded_io::Read`] and [`embedded_io::Write`].
+///
+/// The rest of the driver is reached through [`Self::run`]:
+///
+/// ```ignore
+/// let mut uart = BlockingSc16is752::new(spi, irq, Channel::A);
+/// uart.run(async |uart| uart.init(115_200, 14_745_600).await)?;
+/// ```
+pub struct BlockingSc16is752<Spi, Irq, const BUF: usize = 64> {
+    inner: Sc16is752<Spi, Blocking<Irq>, BUF>,
+}
+
+impl<S, Irq> BlockingSc16is752<Blocking<S>, Irq>
+where
+    S: spi::SpiDevice,
+    Irq: InputPin,
+{
+    /// Creates a driver over a blocking SPI device, with the default 64
+    /// bytes of internal buffering.
+    pub fn new(spi: S, irq: Irq, channel: Channel) -> Self {
+        BlockingSc16is752::from_interface(Blocking(spi), irq, channel)
+    }
+}
+
+impl<Spi, Irq, const BUF: usize> BlockingSc16is752<Spi, Irq, BUF>
+where
+    Spi: RegisterInterface,
+    Irq: InputPin,
+{
+    /// Uses any bus, e.g. `Blocking(spi)` or an [`I2cInterface`](crate::I2cInterface)
+    /// over `Blocking(i2c)`.
+    pub fn from_interface(bus: Spi, irq: Irq, channel: Channel) -> Self {
+        BlockingSc16is752 {
+            inner: Sc16is752::with_capacity(bus, Blocking(irq), channel),
+        }
+    }
+
+    /// Runs `f` on the async driver to completion.
+    pub fn run<R>(
+        &mut self,
+        f: impl AsyncFnOnce(&mut Sc16is752<Spi, Blocking<Irq>, BUF>) -> R,
+    ) -> R {
+        block_on(f(&mut self.inner))
+    }
+
+    pub fn release(self) -> Sc16is752<Spi, Blocking<Irq>, BUF> {
+        self.inner
+    }
+}
+
+impl<Spi: RegisterInterface, Irq, const BUF: usize> ErrorType for BlockingSc16is752<Spi, Irq, BUF> {
+    type Error = Error<Spi::Error>;
+}
+
+impl<Spi: RegisterInterface, Irq: InputPin, const BUF: usize> Read
+    for BlockingSc16is752<Spi, Irq, BUF>
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        block_on(embedded_io_async::Read::read(&mut self.inner, buf))
+    }
+}
+
+impl<Spi: RegisterInterface, Irq: InputPin, const BUF: usize> Write
+    for BlockingSc16is752<Spi, Irq, BUF>
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        block_on(embedded_io_async::Write::write(&mut self.inner, buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        block_on(embedded_io_async::Write::flush(&mut self.inner))
+    }
+}
+
+#[cfg(feature = "nb")]
+mod serial_nb {
+    use embedded_hal_nb::nb;
+    use embedded_hal_nb::serial;
+
+    use super::*;
+
+    impl<SpiErr: core::fmt::Debug> serial::Error for Error<SpiErr> {
+        fn kind(&self) -> serial::ErrorKind {
+            match self {
+                Error::Overrun => serial::ErrorKind::Overrun,
+                Error::Framing => serial::ErrorKind::FrameFormat,
+                Error::Parity => serial::ErrorKind::Parity,
+                _ => serial::ErrorKind::Other,
+            }
+        }
+    }
+
+    impl<Spi: RegisterInterface, Irq, const BUF: usize> serial::ErrorType
+        for BlockingSc16is752<Spi, Irq, BUF>
+    {
+        type Error = Error<Spi::Error>;
+    }
+
+    /// Takes a byte from the RX FIFO (or the driver's buffer), or
+    /// `WouldBlock` if none has arrived.
+    impl<Spi: RegisterInterface, Irq: InputPin, const BUF: usize> serial::Read
+        for BlockingSc16is752<Spi, Irq, BUF>
+    {
+        fn read(&mut self) -> nb::Result<u8, Error<Spi::Error>> {
+            let mut byte = [0];
+            match block_on(self.inner.try_read(&mut byte))? {
+                0 => Err(nb::Error::WouldBlock),
+                _ => Ok(byte[0]),
+            }
+        }
+    }
+
+    /// Queues a byte in the TX FIFO, or returns `WouldBlock` while it is
+    /// full. `flush` returns `WouldBlock` until the transmitter is idle.
+    impl<Spi: RegisterInterface, Irq: InputPin, const BUF: usize> serial::Write
+        for BlockingSc16is752<Spi, Irq, BUF>
+    {
+        fn write(&mut self, word: u8) -> nb::Result<(), Error<Spi::Error>> {
+            match block_on(self.inner.try_write(&[word]))? {
+                0 => Err(nb::Error::WouldBlock),
+                _ => Ok(()),
+            }
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Error<Spi::Error>> {
+            let lsr = block_on(self.inner.read_lsr())?;
+            if lsr.thr_empty() && lsr.thr_tsr_empty() {
+                Ok(())
+            } else {
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::low_level::{LCR, LSR, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockPin, MockSpi, Window};
+
+    #[test]
+    fn test_blocking() {
+        let spi = MockSpi::new();
+        let irq = MockPin::default();
+        let mut uart = BlockingSc16is752::new(spi.clone(), irq, Channel::B);
+
+        uart.run(async |uart| uart.init(9600, 1_843_200).await)
+            .unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, LCR), 0x03);
+
+        spi.set(Channel::B, Window::General, TXLVL, 64);
+        spi.set(Channel::B, Window::General, LSR, 0x60);
+        uart.write_all(b"sync").unwrap();
+        uart.flush().unwrap();
+        assert_eq!(spi.writes(Channel::B, Window::General, THR), b"sync");
+
+        spi.set(Channel::B, Window::General, RXLVL, 2);
+        spi.queue(Channel::B, Window::General, RHR, b"ok");
+        let mut buf = [0; 4];
+        assert_eq!(uart.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ok");
+    }
+
+    #[cfg(feature = "nb")]
+    #[test]
+    fn test_serial_nb() {
+        use embedded_hal_nb::{nb, serial};
+
+        let spi = MockSpi::new();
+        let mut uart = BlockingSc16is752::new(spi.clone(), MockPin::default(), Channel::A);
+        uart.run(async |uart| uart.init(9600, 1_843_200).await)
+            .unwrap();
+
+        spi.set(Channel::A, Window::General, TXLVL, 0);
+        assert!(matches!(
+            serial::Write::write(&mut uart, b'x'),
+            Err(nb::Error::WouldBlock)
+        ));
+        spi.set(Channel::A, Window::General, TXLVL, 1);
+        serial::Write::write(&mut uart, b'x').unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"x");
+
+        spi.set(Channel::A, Window::General, LSR, 0x20);
+        assert!(matches!(
+            serial::Write::flush(&mut uart),
+            Err(nb::Error::WouldBlock)
+        ));
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        serial::Write::flush(&mut uart).unwrap();
+
+        assert!(matches!(
+            serial::Read::read(&mut uart),
+            Err(nb::Error::WouldBlock)
+        ));
+        spi.set(Channel::A, Window::General, RXLVL, 1);
+        spi.queue(Channel::A, Window::General, RHR, b"y");
+        assert_eq!(serial::Read::read(&mut uart).unwrap(), b'y');
+    }
+}
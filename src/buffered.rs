@@ -0,0 +1,338 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use embedded_hal_async::digital::Wait;
+use embedded_io_async::{ErrorType, Read, Write};
+use heapless::Deque;
+
+use crate::interface::RegisterInterface;
+use crate::low_level::Ier;
+use crate::{Error, InterruptSource, Sc16is752};
+
+/// The RX and TX buffers shared by a [`BufferedSc16is752`] and its
+/// [`BufferedRx`]/[`BufferedTx`] handles, `RX` and `TX` bytes large.
+///
+/// The handles and [`BufferedSc16is752::run`] borrow this in turn without
+/// holding the borrow across an await, so they have to run on the same
+/// executor, as with any `RefCell` shared between tasks.
+pub struct BufferedState<const RX: usize, const TX: usize> {
+    inner: RefCell<Buffers<RX, TX>>,
+}
+
+struct Buffers<const RX: usize, const TX: usize> {
+    rx: Deque<u8, RX>,
+    tx: Deque<u8, TX>,
+    rx_waker: Option<Waker>,
+    tx_waker: Option<Waker>,
+    run_waker: Option<Waker>,
+    /// Set by the handles when the runner has something new to do.
+    kick: bool,
+}
+
+impl<const RX: usize, const TX: usize> BufferedState<RX, TX> {
+    pub const fn new() -> Self {
+        BufferedState {
+            inner: RefCell::new(Buffers {
+                rx: Deque::new(),
+                tx: Deque::new(),
+                rx_waker: None,
+                tx_waker: None,
+                run_waker: None,
+                kick: false,
+            }),
+        }
+    }
+
+    /// The reading end of the RX buffer.
+    pub fn rx(&self) -> BufferedRx<'_, RX, TX> {
+        BufferedRx { state: self }
+    }
+
+    /// The writing end of the TX buffer.
+    pub fn tx(&self) -> BufferedTx<'_, RX, TX> {
+        BufferedTx { state: self }
+    }
+}
+
+impl<const RX: usize, const TX: usize> Default for BufferedState<RX, TX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const RX: usize, const TX: usize> Buffers<RX, TX> {
+    fn kick(&mut self) {
+        self.kick = true;
+        if let Some(waker) = self.run_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn register(slot: &mut Option<Waker>, cx: &Context<'_>) {
+    match slot {
+        Some(waker) if waker.will_wake(cx.waker()) => {}
+        _ => *slot = Some(cx.waker().clone()),
+    }
+}
+
+/// A channel whose FIFOs are serviced in the background by [`Self::run`],
+/// which moves data between them and a [`BufferedState`] so that nothing
+/// is lost while the application is busy elsewhere, as long as the buffers
+/// have room.
+pub struct BufferedSc16is752<'a, Spi, Irq, const RX: usize, const TX: usize, const BUF: usize = 64>
+{
+    uart: Sc16is752<Spi, Irq, BUF>,
+    state: &'a BufferedState<RX, TX>,
+    /// The last value written to IER.
+    ier: Option<u8>,
+}
+
+impl<'a, Spi, Irq, const RX: usize, const TX: usize, const BUF: usize>
+    BufferedSc16is752<'a, Spi, Irq, RX, TX, BUF>
+where
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    /// Takes over an initialized channel. Data is read and written through
+    /// [`BufferedState::rx`] and [`BufferedState::tx`].
+    pub fn new(uart: Sc16is752<Spi, Irq, BUF>, state: &'a BufferedState<RX, TX>) -> Self {
+        BufferedSc16is752 {
+            uart,
+            state,
+            ier: None,
+        }
+    }
+
+    /// Services the channel until an error occurs (including one from the
+    /// IRQ pin), waiting on the IRQ pin in between. Call it again to carry
+    /// on after an error.
+    ///
+    /// While the RX buffer is full, data is left in the RX FIFO and the RX
+    /// interrupt is disabled; with flow control enabled the peer then gets
+    /// halted instead of data being lost.
+    pub async fn run(&mut self) -> Result<Infallible, Error<Spi::Error>> {
+        loop {
+            self.service().await?;
+
+            let state = self.state;
+            let mut irq = pin!(self.uart.irq.wait_for_low());
+            poll_fn(|cx| {
+                let mut buffers = state.inner.borrow_mut();
+                if core::mem::take(&mut buffers.kick) {
+                    return Poll::Ready(Ok(()));
+                }
+                register(&mut buffers.run_waker, cx);
+                irq.as_mut().poll(cx)
+            })
+            .await
+            .map_err(Error::irq)?;
+        }
+    }
+
+    /// Gives the channel back.
+    pub fn release(self) -> Sc16is752<Spi, Irq, BUF> {
+        self.uart
+    }
+
+    /// Clears pending interrupts, moves data both ways and enables the
+    /// interrupts needed for what is left to do.
+    async fn service(&mut self) -> Result<(), Error<Spi::Error>> {
+        // A line status interrupt is only cleared by reading LSR
+        if self.uart.poll_interrupt().await? == Some(InterruptSource::ReceiveLineStatusError) {
+            self.uart.read_lsr().await?;
+        }
+
+        let mut chunk = [0; 64];
+
+        let room = RX - self.state.inner.borrow().rx.len();
+        if room > 0 {
+            let len = self.uart.read_fifo(&mut chunk[..room.min(64)]).await?;
+            if len > 0 {
+                let mut buffers = self.state.inner.borrow_mut();
+                for &byte in &chunk[..len] {
+                    // There was room for all of them
+                    buffers.rx.push_back(byte).ok();
+                }
+                if let Some(waker) = buffers.rx_waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        if !self.state.inner.borrow().tx.is_empty() {
+            let space = self.uart.tx_space().await?;
+            let len = {
+                let mut buffers = self.state.inner.borrow_mut();
+                let mut len = 0;
+                while len < space.min(64) {
+                    let Some(byte) = buffers.tx.pop_front() else {
+                        break;
+                    };
+                    chunk[len] = byte;
+                    len += 1;
+                }
+                len
+            };
+            if len > 0 {
+                self.uart
+                    .regs
+                    .write_many_thr(self.uart.channel, &chunk[..len])
+                    .await?;
+                if let Some(waker) = self.state.inner.borrow_mut().tx_waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        let ier = {
+            let buffers = self.state.inner.borrow();
+            Ier::new()
+                .with_receive_holding_register(!buffers.rx.is_full())
+                .with_transmit_holding_register(!buffers.tx.is_empty())
+        };
+        let [bits] = ier.into_bytes();
+        if self.ier != Some(bits) {
+            self.uart.regs.write_ier(self.uart.channel, ier).await?;
+            self.ier = Some(bits);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads from the RX buffer of a [`BufferedSc16is752`]. Line and bus
+/// errors are reported by [`BufferedSc16is752::run`], not here.
+pub struct BufferedRx<'a, const RX: usize, const TX: usize> {
+    state: &'a BufferedState<RX, TX>,
+}
+
+/// Writes to the TX buffer of a [`BufferedSc16is752`].
+pub struct BufferedTx<'a, const RX: usize, const TX: usize> {
+    state: &'a BufferedState<RX, TX>,
+}
+
+impl<const RX: usize, const TX: usize> ErrorType for BufferedRx<'_, RX, TX> {
+    type Error = Infallible;
+}
+
+impl<const RX: usize, const TX: usize> ErrorType for BufferedTx<'_, RX, TX> {
+    type Error = Infallible;
+}
+
+impl<const RX: usize, const TX: usize> Read for BufferedRx<'_, RX, TX> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        poll_fn(|cx| {
+            let mut buffers = self.state.inner.borrow_mut();
+            if buffers.rx.is_empty() {
+                register(&mut buffers.rx_waker, cx);
+                return Poll::Pending;
+            }
+
+            let was_full = buffers.rx.is_full();
+            let mut len = 0;
+            while len < buf.len() {
+                let Some(byte) = buffers.rx.pop_front() else {
+                    break;
+                };
+                buf[len] = byte;
+                len += 1;
+            }
+            // The runner stopped reading the FIFO while the buffer was full
+            if was_full {
+                buffers.kick();
+            }
+            Poll::Ready(Ok(len))
+        })
+        .await
+    }
+}
+
+impl<const RX: usize, const TX: usize> Write for BufferedTx<'_, RX, TX> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        poll_fn(|cx| {
+            let mut buffers = self.state.inner.borrow_mut();
+            if buffers.tx.is_full() {
+                register(&mut buffers.tx_waker, cx);
+                return Poll::Pending;
+            }
+
+            let mut len = 0;
+            while len < buf.len() && buffers.tx.push_back(buf[len]).is_ok() {
+                len += 1;
+            }
+            buffers.kick();
+            Poll::Ready(Ok(len))
+        })
+        .await
+    }
+
+    /// Waits until the runner has moved everything to the TX FIFO. The chip
+    /// may still be sending the last 64 bytes.
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        poll_fn(|cx| {
+            let mut buffers = self.state.inner.borrow_mut();
+            if buffers.tx.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            register(&mut buffers.tx_waker, cx);
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Channel;
+    use crate::low_level::{IER, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockPin, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_buffered() {
+        let spi = MockSpi::new();
+        let irq = MockPin::default();
+        let mut uart = Sc16is752::new(spi.clone(), irq.clone(), Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        let state = BufferedState::<4, 8>::new();
+        let mut buffered = BufferedSc16is752::new(uart, &state);
+        let (mut rx, mut tx) = (state.rx(), state.tx());
+
+        spi.set(Channel::A, Window::General, RXLVL, 6);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef");
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut run = pin!(buffered.run());
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+
+        // The RX buffer filled up, so the rest stays in the FIFO
+        let mut buf = [0; 8];
+        assert_eq!(block_on(rx.read(&mut buf)).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x00);
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+
+        block_on(tx.write_all(b"hello")).unwrap();
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"hello");
+        block_on(tx.flush()).unwrap();
+
+        assert_eq!(block_on(rx.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ef");
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+    }
+}
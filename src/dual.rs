@@ -0,0 +1,232 @@
+use embedded_hal_async::digital::Wait;
+
+use crate::Error;
+use crate::interface::RegisterInterface;
+use crate::low_level::{Channel, InterruptSource, RegisterWrapper};
+
+/// A handle on both channels of the chip at once, for moving data between
+/// them (e.g. a UART-to-UART bridge).
+pub struct Sc16is752Dual<Spi, Irq> {
+    regs: RegisterWrapper<Spi>,
+    irq: Irq,
+    /// The channel [`Self::service_fair`] looks at first next time.
+    next: Channel,
+}
+
+/// What [`Sc16is752Dual::service_fair`] did for one channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelService {
+    /// Bytes bridged from this channel's RX FIFO to the other's TX FIFO.
+    pub bytes_moved: usize,
+    /// Interrupt sources handled.
+    pub events: u8,
+}
+
+/// Result of one [`Sc16is752Dual::service_fair`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ServiceReport {
+    /// The channel that was serviced first.
+    pub first: Channel,
+    pub a: ChannelService,
+    pub b: ChannelService,
+}
+
+impl<Spi, Irq> Sc16is752Dual<Spi, Irq>
+where
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    pub fn new(spi: Spi, irq: Irq) -> Self {
+        Sc16is752Dual {
+            regs: RegisterWrapper::new(spi),
+            irq,
+            next: Channel::A,
+        }
+    }
+
+    /// Waits until the IRQ line is asserted by either channel.
+    pub async fn wait_for_irq(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.irq.wait_for_low().await.map_err(Error::irq)
+    }
+
+    /// Returns the number of bytes waiting in `rx_channel`'s RX FIFO and the
+    /// free space in `tx_channel`'s TX FIFO, i.e. how much a bridge can move
+    /// from one to the other in one go.
+    pub async fn fifo_balance(
+        &mut self,
+        rx_channel: Channel,
+        tx_channel: Channel,
+    ) -> Result<(u8, u8), Error<Spi::Error>> {
+        let rxlvl = self.regs.read_rxlvl(rx_channel).await?;
+        let txlvl = self.regs.read_txlvl(tx_channel).await?;
+
+        Ok((rxlvl, txlvl))
+    }
+
+    /// Moves up to `max` bytes from `from`'s RX FIFO to `to`'s TX FIFO with
+    /// one RHR burst and one THR burst, limited by [`Self::fifo_balance`].
+    /// Returns the number of bytes moved.
+    pub async fn bridge(
+        &mut self,
+        from: Channel,
+        to: Channel,
+        max: usize,
+    ) -> Result<usize, Error<Spi::Error>> {
+        let (rxlvl, txlvl) = self.fifo_balance(from, to).await?;
+        let len = usize::from(rxlvl.min(txlvl)).min(max).min(64);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut buf = [0; 64];
+        self.regs.read_many_rhr(from, &mut buf[..len]).await?;
+        self.regs.write_many_thr(to, &buf[..len]).await?;
+
+        Ok(len)
+    }
+
+    /// Services pending interrupts of both channels as a bridge: received
+    /// data is moved to the other channel with [`Self::bridge`], line and
+    /// modem status are read to clear them.
+    ///
+    /// The channel serviced first alternates between calls, so that under
+    /// load neither channel gets to fill the other's TX FIFO first every
+    /// time.
+    pub async fn service_fair(&mut self) -> Result<ServiceReport, Error<Spi::Error>> {
+        let first = self.next;
+        let second = other(first);
+        self.next = second;
+
+        let first_report = self.service_channel(first).await?;
+        let second_report = self.service_channel(second).await?;
+        let (a, b) = match first {
+            Channel::A => (first_report, second_report),
+            Channel::B => (second_report, first_report),
+        };
+
+        Ok(ServiceReport { first, a, b })
+    }
+
+    async fn service_channel(
+        &mut self,
+        channel: Channel,
+    ) -> Result<ChannelService, Error<Spi::Error>> {
+        let mut report = ChannelService::default();
+
+        let iir = self.regs.read_iir(channel).await?;
+        if iir.no_interrupt() {
+            return Ok(report);
+        }
+        report.events += 1;
+
+        match iir.source_or_err() {
+            Ok(InterruptSource::RhrInterrupt | InterruptSource::ReceiverTimeout) => {
+                report.bytes_moved = self.bridge(channel, other(channel), 64).await?;
+            }
+            Ok(InterruptSource::ReceiveLineStatusError) => {
+                self.regs.read_lsr(channel).await?;
+            }
+            Ok(InterruptSource::ModemInterrupt) => {
+                self.regs.read_msr(channel).await?;
+            }
+            // Reading IIR is enough to clear the rest
+            _ => {}
+        }
+
+        Ok(report)
+    }
+}
+
+fn other(channel: Channel) -> Channel {
+    match channel {
+        Channel::A => Channel::B,
+        Channel::B => Channel::A,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::low_level::{IIR, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockIrq, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_fifo_balance() {
+        let spi = MockSpi::new();
+        let mut dual = Sc16is752Dual::new(spi.clone(), MockIrq);
+
+        spi.set(Channel::A, Window::General, RXLVL, 12);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::B, Window::General, RXLVL, 0);
+        spi.set(Channel::B, Window::General, TXLVL, 40);
+
+        assert_eq!(
+            block_on(dual.fifo_balance(Channel::A, Channel::B)).unwrap(),
+            (12, 40)
+        );
+        assert_eq!(
+            block_on(dual.fifo_balance(Channel::B, Channel::A)).unwrap(),
+            (0, 64)
+        );
+    }
+
+    #[test]
+    fn test_bridge() {
+        let spi = MockSpi::new();
+        let mut dual = Sc16is752Dual::new(spi.clone(), MockIrq);
+
+        spi.set(Channel::B, Window::General, RXLVL, 5);
+        spi.set(Channel::A, Window::General, TXLVL, 3);
+        spi.queue(Channel::B, Window::General, RHR, b"hello");
+
+        assert_eq!(
+            block_on(dual.bridge(Channel::B, Channel::A, 16)).unwrap(),
+            3
+        );
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"hel");
+
+        let bursts: heapless::Vec<_, 4> = spi
+            .log()
+            .into_iter()
+            .filter(|a| a.reg == RHR)
+            .map(|a| (a.read, a.channel, a.data.len()))
+            .collect();
+        assert_eq!(bursts, [(true, Channel::B, 3), (false, Channel::A, 3)]);
+
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        assert_eq!(block_on(dual.bridge(Channel::B, Channel::A, 1)).unwrap(), 1);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"hell");
+    }
+
+    #[test]
+    fn test_service_fair() {
+        let spi = MockSpi::new();
+        let mut dual = Sc16is752Dual::new(spi.clone(), MockIrq);
+
+        // Both channels always have data and room
+        for channel in [Channel::A, Channel::B] {
+            spi.set(channel, Window::General, IIR, 0xC4);
+            spi.set(channel, Window::General, RXLVL, 8);
+            spi.set(channel, Window::General, TXLVL, 64);
+        }
+
+        let mut moved = [0; 2];
+        for round in 0..4 {
+            let report = block_on(dual.service_fair()).unwrap();
+            let expected = if round % 2 == 0 {
+                Channel::A
+            } else {
+                Channel::B
+            };
+            assert_eq!(report.first, expected);
+            assert_eq!((report.a.events, report.b.events), (1, 1));
+            moved[0] += report.a.bytes_moved;
+            moved[1] += report.b.bytes_moved;
+        }
+        assert_eq!(moved, [32, 32]);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR).len(), 32);
+        assert_eq!(spi.writes(Channel::B, Window::General, THR).len(), 32);
+    }
+}
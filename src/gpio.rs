@@ -0,0 +1,447 @@
+use embedded_hal::digital::ErrorType;
+use embedded_hal_async::digital::Wait;
+
+use crate::interface::RegisterInterface;
+use crate::low_level::RegisterWrapper;
+use crate::{Error, yield_now};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinDir {
+    Input,
+    Output,
+}
+
+/// The eight GPIO pins as a small port expander, obtained from
+/// [`Sc16is752::gpio_port`](crate::Sc16is752::gpio_port). Index `n` of every
+/// array is GPIO`n`.
+///
+/// Levels are logical, see
+/// [`Sc16is752::set_gpio_active_low`](crate::Sc16is752::set_gpio_active_low).
+pub struct GpioPort<'a, Spi> {
+    regs: &'a mut RegisterWrapper<Spi>,
+    active_low: u8,
+    /// Cleared on variants without GPIOs, making every method fail with
+    /// [`Error::Unsupported`].
+    available: bool,
+}
+
+impl<'a, Spi: RegisterInterface> GpioPort<'a, Spi> {
+    pub(crate) fn new(regs: &'a mut RegisterWrapper<Spi>, active_low: u8, available: bool) -> Self {
+        GpioPort {
+            regs,
+            active_low,
+            available,
+        }
+    }
+
+    fn regs(&mut self) -> Result<&mut RegisterWrapper<Spi>, Error<Spi::Error>> {
+        if !self.available {
+            return Err(Error::Unsupported);
+        }
+        Ok(self.regs)
+    }
+
+    pub async fn set_directions(&mut self, dirs: [PinDir; 8]) -> Result<(), Error<Spi::Error>> {
+        self.regs()?
+            .write_iodir(to_mask(dirs.map(|dir| dir == PinDir::Output)))
+            .await
+    }
+
+    pub async fn directions(&mut self) -> Result<[PinDir; 8], Error<Spi::Error>> {
+        let iodir = self.regs()?.read_iodir().await?;
+
+        Ok(from_mask(iodir).map(|output| {
+            if output {
+                PinDir::Output
+            } else {
+                PinDir::Input
+            }
+        }))
+    }
+
+    /// Sets the output levels. Levels of pins configured as inputs are
+    /// ignored by the chip.
+    pub async fn write_all(&mut self, levels: [bool; 8]) -> Result<(), Error<Spi::Error>> {
+        let levels = to_mask(levels) ^ self.active_low;
+        self.regs()?.write_iostate(levels).await
+    }
+
+    pub async fn read_all(&mut self) -> Result<[bool; 8], Error<Spi::Error>> {
+        let levels = self.regs()?.read_iostate().await?;
+        Ok(from_mask(levels ^ self.active_low))
+    }
+
+    /// Sets the level of GPIO`pin`, leaving the other outputs as they are.
+    pub async fn write_pin(&mut self, pin: u8, level: bool) -> Result<(), Error<Spi::Error>> {
+        let mask = pin_mask(pin)?;
+        let electrical = level != (self.active_low & mask != 0);
+
+        let levels = self.regs()?.read_iostate().await?;
+        let levels = if electrical {
+            levels | mask
+        } else {
+            levels & !mask
+        };
+        self.regs()?.write_iostate(levels).await
+    }
+
+    pub async fn read_pin(&mut self, pin: u8) -> Result<bool, Error<Spi::Error>> {
+        let mask = pin_mask(pin)?;
+        let levels = self.regs()?.read_iostate().await?;
+        Ok((levels ^ self.active_low) & mask != 0)
+    }
+
+    /// Enables or disables latching of input changes: while enabled, IOState
+    /// holds the levels captured at a change until it is read.
+    pub async fn set_input_latch(&mut self, enabled: bool) -> Result<(), Error<Spi::Error>> {
+        let ioc = self.regs()?.read_iocontrol().await?;
+        self.regs()?
+            .write_iocontrol(ioc.with_io_latch(enabled))
+            .await
+    }
+
+    /// Sets which inputs raise the input change interrupt (IOIntEna) as a
+    /// mask, bit `n` being GPIO`n`. The interrupt shares the IRQ line with
+    /// the UART interrupts and is cleared by reading IOState.
+    pub async fn set_input_interrupts(&mut self, mask: u8) -> Result<(), Error<Spi::Error>> {
+        self.regs()?.write_iointena(mask).await
+    }
+
+    /// Narrows the port down to GPIO`pin`, for handing a single pin to code
+    /// that drives e.g. a reset line or an LED.
+    pub fn into_pin(self, pin: u8) -> Result<GpioPin<'a, Spi>, Error<Spi::Error>> {
+        pin_mask::<Spi::Error>(pin)?;
+        if !self.available {
+            return Err(Error::Unsupported);
+        }
+        Ok(GpioPin { port: self, pin })
+    }
+}
+
+/// A single GPIO, obtained from [`GpioPort::into_pin`]. The methods mirror
+/// the `embedded-hal` digital traits, which cannot be implemented here as
+/// they are blocking and every access goes over the bus.
+pub struct GpioPin<'a, Spi> {
+    port: GpioPort<'a, Spi>,
+    pin: u8,
+}
+
+impl<Spi: RegisterInterface> GpioPin<'_, Spi> {
+    pub async fn set_direction(&mut self, dir: PinDir) -> Result<(), Error<Spi::Error>> {
+        let mask = 1 << self.pin;
+        let iodir = self.port.regs()?.read_iodir().await?;
+        let iodir = match dir {
+            PinDir::Output => iodir | mask,
+            PinDir::Input => iodir & !mask,
+        };
+        self.port.regs()?.write_iodir(iodir).await
+    }
+
+    pub async fn set_high(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.port.write_pin(self.pin, true).await
+    }
+
+    pub async fn set_low(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.port.write_pin(self.pin, false).await
+    }
+
+    pub async fn toggle(&mut self) -> Result<(), Error<Spi::Error>> {
+        let level = self.is_high().await?;
+        self.port.write_pin(self.pin, !level).await
+    }
+
+    pub async fn is_high(&mut self) -> Result<bool, Error<Spi::Error>> {
+        self.port.read_pin(self.pin).await
+    }
+
+    pub async fn is_low(&mut self) -> Result<bool, Error<Spi::Error>> {
+        self.is_high().await.map(|level| !level)
+    }
+}
+
+/// A GPIO input that can be awaited, obtained from
+/// [`Sc16is752::gpio_input`](crate::Sc16is752::gpio_input). The pin must
+/// be configured as an input.
+///
+/// While waiting, the pin's input change interrupt (IOIntEna) is enabled
+/// and the IRQ pin is awaited; the previous IOIntEna is restored when the
+/// wait completes. The IRQ line is shared with the UART interrupts, so one
+/// of those pending makes the wait poll IOState until it is serviced.
+/// Pulses shorter than an IOState read can be missed unless
+/// [`GpioPort::set_input_latch`] is enabled.
+pub struct GpioInput<'a, Spi, Irq> {
+    regs: &'a mut RegisterWrapper<Spi>,
+    irq: &'a mut Irq,
+    mask: u8,
+    active_low: bool,
+}
+
+impl<'a, Spi: RegisterInterface, Irq: Wait> GpioInput<'a, Spi, Irq> {
+    pub(crate) fn new(
+        regs: &'a mut RegisterWrapper<Spi>,
+        irq: &'a mut Irq,
+        pin: u8,
+        active_low: bool,
+    ) -> Result<Self, Error<Spi::Error>> {
+        Ok(GpioInput {
+            regs,
+            irq,
+            mask: pin_mask(pin)?,
+            active_low,
+        })
+    }
+
+    pub async fn is_high(&mut self) -> Result<bool, Error<Spi::Error>> {
+        let levels = self.regs.read_iostate().await?;
+        Ok((levels & self.mask != 0) != self.active_low)
+    }
+
+    pub async fn is_low(&mut self) -> Result<bool, Error<Spi::Error>> {
+        self.is_high().await.map(|level| !level)
+    }
+
+    async fn wait_for(&mut self, high: bool) -> Result<(), Error<Spi::Error>> {
+        let enabled = self.regs.read_iointena().await?;
+        self.regs.write_iointena(enabled | self.mask).await?;
+
+        let result = async {
+            // Checked after enabling the interrupt, so no change is missed
+            while self.is_high().await? != high {
+                self.irq.wait_for_low().await.map_err(Error::irq)?;
+                yield_now().await;
+            }
+            Ok(())
+        }
+        .await;
+
+        self.regs.write_iointena(enabled).await?;
+        result
+    }
+}
+
+impl<SpiErr: core::fmt::Debug> embedded_hal::digital::Error for Error<SpiErr> {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        match self {
+            Error::Irq(kind) => *kind,
+            _ => embedded_hal::digital::ErrorKind::Other,
+        }
+    }
+}
+
+impl<Spi: RegisterInterface, Irq> ErrorType for GpioInput<'_, Spi, Irq> {
+    type Error = Error<Spi::Error>;
+}
+
+impl<Spi: RegisterInterface, Irq: Wait> Wait for GpioInput<'_, Spi, Irq> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await?;
+        self.wait_for(true).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await?;
+        self.wait_for(false).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        let high = self.is_high().await?;
+        self.wait_for(!high).await
+    }
+}
+
+/// The IODir/IOState bit of GPIO`pin`.
+pub(crate) fn pin_mask<SpiErr>(pin: u8) -> Result<u8, Error<SpiErr>> {
+    1u8.checked_shl(pin.into()).ok_or(Error::InvalidPin)
+}
+
+fn to_mask(bits: [bool; 8]) -> u8 {
+    bits.iter()
+        .enumerate()
+        .fold(0, |mask, (pin, &bit)| mask | (u8::from(bit) << pin))
+}
+
+fn from_mask(mask: u8) -> [bool; 8] {
+    core::array::from_fn(|pin| mask & (1 << pin) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::low_level::{IIR, IOCONTROL, IODIR, IOINTENA, IOSTATE};
+    use crate::mock::{MockDelay, MockIrq, MockSpi, Window, block_on};
+    use crate::{Channel, GpioDir, Sc16is752};
+
+    #[test]
+    fn test_gpio_port() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        let mut port = uart.gpio_port();
+
+        let mut dirs = [PinDir::Input; 8];
+        dirs[0] = PinDir::Output;
+        dirs[5] = PinDir::Output;
+        block_on(port.set_directions(dirs)).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IODIR),
+            [0b0010_0001]
+        );
+        assert_eq!(block_on(port.directions()).unwrap(), dirs);
+
+        spi.set(Channel::A, Window::General, IOSTATE, 0b1000_0010);
+        let levels = block_on(port.read_all()).unwrap();
+        assert_eq!(
+            levels,
+            [false, true, false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_shared_registers() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        let dirs = GpioDir::new().with_gpio0(true).with_gpio6(true);
+        block_on(uart.set_gpio_direction(dirs)).unwrap();
+        block_on(uart.set_gpio(0b0100_0000)).unwrap();
+        spi.set(Channel::A, Window::General, IOSTATE, 0b0100_0010);
+        assert_eq!(block_on(uart.read_gpio()).unwrap(), 0b0100_0010);
+
+        // Channel B's handle still addresses the channel A registers
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IODIR),
+            [0b0100_0001]
+        );
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IOSTATE),
+            [0b0100_0000]
+        );
+        assert!(spi.log().iter().all(|a| a.channel == Channel::A));
+    }
+
+    #[test]
+    fn test_gpio_pin() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        uart.set_gpio_active_low(0b0001_0000);
+        spi.set(Channel::A, Window::General, IODIR, 0b0000_0001);
+        spi.set(Channel::A, Window::General, IOSTATE, 0b0001_0000);
+
+        let mut reset = uart.gpio_port().into_pin(4).unwrap();
+        block_on(reset.set_direction(PinDir::Output)).unwrap();
+        assert!(block_on(reset.is_low()).unwrap());
+        block_on(reset.set_high()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOSTATE), 0b0000_0000);
+        block_on(reset.toggle()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOSTATE), 0b0001_0000);
+        assert_eq!(spi.get(Channel::A, Window::General, IODIR), 0b0001_0001);
+
+        assert!(matches!(
+            uart.gpio_port().into_pin(8),
+            Err(Error::InvalidPin)
+        ));
+    }
+
+    #[test]
+    fn test_read_latched() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, IOCONTROL, 0x02);
+
+        // Without the latch IOState holds nothing but the live levels
+        assert!(matches!(
+            block_on(uart.read_gpio_latched()),
+            Err(Error::LatchDisabled)
+        ));
+        assert!(!spi.log().iter().any(|a| a.reg == IOSTATE));
+
+        block_on(uart.gpio_port().set_input_latch(true)).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, IOCONTROL), [0x03]);
+
+        // A pulse on GPIO2 whose interrupt was serviced, then one that was not
+        spi.set(Channel::A, Window::General, IIR, 0x30);
+        spi.queue(Channel::A, Window::General, IOSTATE, &[0b0000_0100]);
+        assert_eq!(block_on(uart.read_gpio_latched()).unwrap(), 0b0000_0100);
+
+        spi.set(Channel::A, Window::General, IIR, 0x01);
+        spi.queue(Channel::A, Window::General, IOSTATE, &[0b0000_0100]);
+        assert_eq!(block_on(uart.read_gpio_latched()).unwrap(), 0b0000_0100);
+        assert_eq!(block_on(uart.read_gpio_latched()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pulse_gpio() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        spi.set(Channel::A, Window::General, IODIR, 0b0000_1000);
+        spi.set(Channel::A, Window::General, IOSTATE, 0b0000_1001);
+
+        let mut delay = MockDelay::default();
+        block_on(uart.pulse_gpio(3, Duration::from_micros(50), &mut delay)).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IOSTATE),
+            [0b0000_0001, 0b0000_1001]
+        );
+        assert_eq!(delay.total_ns, 50_000);
+
+        let result = block_on(uart.pulse_gpio(0, Duration::from_micros(50), &mut delay));
+        assert!(matches!(result, Err(Error::NotAnOutput)));
+        let result = block_on(uart.pulse_gpio(8, Duration::from_micros(50), &mut delay));
+        assert!(matches!(result, Err(Error::InvalidPin)));
+    }
+
+    #[test]
+    fn test_gpio_input_wait() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        uart.set_gpio_active_low(0b1000_0000);
+        spi.set(Channel::A, Window::General, IOINTENA, 0b0000_0001);
+
+        let mut input = uart.gpio_input(2).unwrap();
+        spi.queue(Channel::A, Window::General, IOSTATE, &[0x00, 0x00, 0x04]);
+        block_on(input.wait_for_high()).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IOINTENA),
+            [0b0000_0101, 0b0000_0001]
+        );
+
+        // GPIO7 is active-low, so a falling edge reads 0x80 then 0x00
+        let mut input = uart.gpio_input(7).unwrap();
+        spi.queue(Channel::A, Window::General, IOSTATE, &[0x80, 0x80, 0x00]);
+        block_on(input.wait_for_rising_edge()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOINTENA), 0b0000_0001);
+
+        assert!(matches!(uart.gpio_input(8), Err(Error::InvalidPin)));
+    }
+
+    #[test]
+    fn test_active_low_pins() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        uart.set_gpio_active_low(0b0000_0110);
+        spi.set(Channel::A, Window::General, IOSTATE, 0b0000_0011);
+
+        // Asserting active-low GPIO1 drives it low
+        block_on(uart.write_gpio_pin(1, true)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOSTATE), 0b0000_0001);
+        assert!(block_on(uart.read_gpio_pin(1)).unwrap());
+        block_on(uart.write_gpio_pin(0, false)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOSTATE), 0b0000_0000);
+
+        let mut levels = [false; 8];
+        levels[2] = true;
+        block_on(uart.gpio_port().write_all(levels)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOSTATE), 0b0000_0010);
+        assert_eq!(block_on(uart.gpio_port().read_all()).unwrap(), levels);
+    }
+}
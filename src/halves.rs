@@ -0,0 +1,213 @@
+use core::time::Duration;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+
+use crate::interface::RegisterInterface;
+use crate::low_level::{Channel, RegisterWrapper};
+use crate::{Error, Sc16is752, char_time, delay_for};
+
+/// The transmit side of a channel, from [`Sc16is752::split_halves`].
+///
+/// The IRQ pin stays with the [`RxHalf`], so this half polls TXLVL (and LSR
+/// in [`Write::flush`]), sleeping on its delay for as many character times
+/// as the transmitter needs to make progress in between. The FIFO enable,
+/// baud rate and framing are taken over at the split, so change them on the
+/// unsplit driver. Reading LSR clears its overrun flag, so an overrun that
+/// happens during a flush may not reach the RX half.
+pub struct TxHalf<Spi, D> {
+    regs: RegisterWrapper<Spi>,
+    channel: Channel,
+    fifo_enabled: bool,
+    char_time: Duration,
+    delay: D,
+}
+
+/// The receive side of a channel, from [`Sc16is752::split_halves`]. Reads
+/// behave exactly as on the unsplit driver.
+pub struct RxHalf<Spi, Irq, const BUF: usize = 64> {
+    uart: Sc16is752<Spi, Irq, BUF>,
+}
+
+impl<Spi, Irq, const BUF: usize> Sc16is752<Spi, Irq, BUF>
+where
+    Spi: RegisterInterface + Clone,
+    Irq: Wait,
+{
+    /// Splits an initialized channel into halves that can be used from
+    /// different tasks, e.g. a read loop and a write loop. Like
+    /// [`Sc16is752::split`], the halves share the SPI device through clones
+    /// of it. The TX half waits on `delay` between polls.
+    pub fn split_halves<D: DelayNs>(self, delay: D) -> (TxHalf<Spi, D>, RxHalf<Spi, Irq, BUF>) {
+        let tx = TxHalf {
+            regs: self.regs.clone(),
+            channel: self.channel,
+            fifo_enabled: self.fcr.enable(),
+            char_time: char_time(self.baud_rate, self.line_config),
+            delay,
+        };
+        (tx, RxHalf { uart: self })
+    }
+}
+
+impl<Spi, Irq, const BUF: usize> RxHalf<Spi, Irq, BUF>
+where
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    /// Puts the driver back together, returning the TX half's delay too.
+    pub fn unsplit<D>(self, tx: TxHalf<Spi, D>) -> (Sc16is752<Spi, Irq, BUF>, D) {
+        (self.uart, tx.delay)
+    }
+}
+
+impl<Spi: RegisterInterface, D> TxHalf<Spi, D> {
+    /// Free space in the TX FIFO, like the driver's: with the FIFO disabled
+    /// TXLVL is meaningless and THR empty stands in for a one-byte FIFO.
+    async fn tx_space(&mut self) -> Result<usize, Error<Spi::Error>> {
+        if !self.fifo_enabled {
+            let lsr = self.regs.read_lsr(self.channel).await?;
+            return Ok(usize::from(lsr.thr_empty()));
+        }
+
+        Ok(usize::from(self.regs.read_txlvl(self.channel).await?))
+    }
+}
+
+impl<Spi: RegisterInterface, D> ErrorType for TxHalf<Spi, D> {
+    type Error = Error<Spi::Error>;
+}
+
+impl<Spi: RegisterInterface, D: DelayNs> Write for TxHalf<Spi, D> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let space = self.tx_space().await?;
+            if space > 0 {
+                let len = buf.len().min(space);
+                self.regs.write_many_thr(self.channel, &buf[..len]).await?;
+                return Ok(len);
+            }
+
+            // A full FIFO frees a byte per character time
+            delay_for(&mut self.delay, self.char_time).await;
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        loop {
+            let lsr = self.regs.read_lsr(self.channel).await?;
+            if lsr.thr_empty() && lsr.thr_tsr_empty() {
+                return Ok(());
+            }
+
+            // Whatever the FIFO holds goes out after the shift register
+            let queued = match (lsr.thr_empty(), self.fifo_enabled) {
+                (true, _) => 0,
+                (false, true) => 64 - usize::from(self.regs.read_txlvl(self.channel).await?),
+                (false, false) => 1,
+            };
+            let characters = u32::try_from(queued + 1).unwrap_or(u32::MAX);
+            delay_for(&mut self.delay, self.char_time * characters).await;
+        }
+    }
+}
+
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> ErrorType for RxHalf<Spi, Irq, BUF> {
+    type Error = Error<Spi::Error>;
+}
+
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> Read for RxHalf<Spi, Irq, BUF> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.uart.read(buf).await
+    }
+}
+
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> BufRead for RxHalf<Spi, Irq, BUF> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.uart.fill_buf().await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.uart.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::low_level::{LSR, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockDelay, MockIrq, MockSpi, Window, block_on};
+    use crate::yield_now;
+
+    /// A delay that lets the test run in between, like a real timer.
+    #[derive(Default)]
+    struct YieldingDelay(MockDelay);
+
+    impl DelayNs for YieldingDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            yield_now().await;
+            self.0.delay_ns(ns).await;
+        }
+    }
+
+    #[test]
+    fn test_split_halves() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+        let (mut tx, mut rx) = uart.split_halves(YieldingDelay::default());
+
+        // TX is blocked on a full FIFO while RX gets data
+        spi.set(Channel::A, Window::General, TXLVL, 0);
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        spi.queue(Channel::A, Window::General, RHR, b"ok");
+
+        let mut cx = Context::from_waker(Waker::noop());
+        {
+            let mut write = pin!(tx.write_all(b"hi"));
+            assert!(write.as_mut().poll(&mut cx).is_pending());
+
+            let mut buf = [0; 4];
+            assert_eq!(block_on(rx.read(&mut buf)).unwrap(), 2);
+            assert_eq!(&buf[..2], b"ok");
+
+            spi.set(Channel::A, Window::General, TXLVL, 64);
+            assert!(matches!(write.as_mut().poll(&mut cx), Poll::Ready(Ok(()))));
+            assert_eq!(spi.writes(Channel::A, Window::General, THR), b"hi");
+        }
+        // The full FIFO was polled once per character time, 1.04 ms at 9600
+        assert_eq!(tx.delay.0.calls, 1);
+        assert_eq!(tx.delay.0.total_ns, 1_041_666);
+
+        // Two bytes queued behind the shift register: three characters
+        spi.set(Channel::A, Window::General, TXLVL, 62);
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x60]);
+        block_on(tx.flush()).unwrap();
+        assert_eq!(tx.delay.0.total_ns, 4 * 1_041_666);
+    }
+
+    #[test]
+    fn test_tx_half_unbuffered() {
+        let spi = MockSpi::new();
+        let uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        let (mut tx, rx) = uart.split_halves(MockDelay::default());
+
+        // THR empty stands in for TXLVL, which reads 0 here
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x20]);
+        assert_eq!(block_on(tx.write(b"ab")).unwrap(), 1);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"a");
+        assert!(!spi.log().iter().any(|a| a.reg == TXLVL));
+
+        let (_, delay) = rx.unsplit(tx);
+        assert_eq!(delay.calls, 1);
+    }
+}
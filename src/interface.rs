@@ -0,0 +1,212 @@
+use embedded_hal_async::{
+    i2c::{self, I2c},
+    spi::{Operation, SpiDevice},
+};
+
+use crate::low_level::{Channel, MAX_DUMMY_BYTES, Rab, ReadWrite};
+
+/// The bus the chip is attached to. Implemented for every [`SpiDevice`], and
+/// for I2C by [`I2cInterface`].
+///
+/// Each call is one register access: one chip-select frame on SPI, one
+/// transfer on I2C. Only THR and RHR take more than one byte per access.
+#[allow(async_fn_in_trait)]
+pub trait RegisterInterface {
+    type Error: core::fmt::Debug;
+
+    async fn write_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        data: &[u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Reads `data.len()` bytes from `reg`. `turnaround` is the number of
+    /// dummy bytes between the command and the data, on buses that have
+    /// them.
+    async fn read_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        turnaround: usize,
+        data: &mut [u8],
+    ) -> Result<(), Self::Error>;
+}
+
+impl<Spi: SpiDevice> RegisterInterface for Spi {
+    type Error = Spi::Error;
+
+    async fn write_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        let [rab] = Rab::new()
+            .with_rw(ReadWrite::Write)
+            .with_register(reg)
+            .with_channel(channel)
+            .into_bytes();
+
+        self.transaction(&mut [Operation::Write(&[rab]), Operation::Write(data)])
+            .await
+    }
+
+    async fn read_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        turnaround: usize,
+        data: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let mut header = [0u8; 1 + MAX_DUMMY_BYTES as usize];
+        [header[0]] = Rab::new()
+            .with_rw(ReadWrite::Read)
+            .with_register(reg)
+            .with_channel(channel)
+            .into_bytes();
+        let len = 1 + turnaround.min(MAX_DUMMY_BYTES.into());
+
+        self.transaction(&mut [Operation::Write(&header[..len]), Operation::Read(data)])
+            .await
+    }
+}
+
+/// What an address pin (A0 or A1) is tied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressPin {
+    Vdd,
+    Vss,
+    Scl,
+    Sda,
+}
+
+/// The chip on an I2C bus (I2C/SPI pin tied high), for passing to
+/// [`Sc16is752::new`](crate::Sc16is752::new) in place of an SPI device.
+pub struct I2cInterface<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> I2cInterface<I2C> {
+    /// Uses the 7-bit address selected by how A1 and A0 are wired, from
+    /// 0x48 (both to VDD) to 0x57 (both to SDA).
+    pub fn new(i2c: I2C, a1: AddressPin, a0: AddressPin) -> Self {
+        I2cInterface {
+            i2c,
+            address: 0x48 + 4 * a1 as u8 + a0 as u8,
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The register address byte: the RAB without the R/W bit, which I2C
+    /// carries in the slave address instead.
+    fn subaddress(reg: u8, channel: Channel) -> u8 {
+        let [subaddress] = Rab::new()
+            .with_register(reg)
+            .with_channel(channel)
+            .into_bytes();
+        subaddress
+    }
+}
+
+impl<I2C: I2c> RegisterInterface for I2cInterface<I2C> {
+    type Error = I2C::Error;
+
+    async fn write_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        let subaddress = [Self::subaddress(reg, channel)];
+
+        // Adjacent writes go out as one transfer, without a repeated start
+        self.i2c
+            .transaction(
+                self.address,
+                &mut [
+                    i2c::Operation::Write(&subaddress),
+                    i2c::Operation::Write(data),
+                ],
+            )
+            .await
+    }
+
+    async fn read_register(
+        &mut self,
+        reg: u8,
+        channel: Channel,
+        _turnaround: usize,
+        data: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let subaddress = [Self::subaddress(reg, channel)];
+        self.i2c.write_read(self.address, &subaddress, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_async::{Read, Write};
+
+    use super::*;
+    use crate::Sc16is752;
+    use crate::low_level::{LCR, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockI2c, MockIrq, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_i2c_address() {
+        let spi = MockSpi::new();
+        let i2c = |a1, a0| {
+            I2cInterface::new(
+                MockI2c {
+                    spi: spi.clone(),
+                    address: 0,
+                },
+                a1,
+                a0,
+            )
+            .address()
+        };
+
+        assert_eq!(i2c(AddressPin::Vdd, AddressPin::Vdd), 0x48);
+        assert_eq!(i2c(AddressPin::Vss, AddressPin::Vss), 0x4D);
+        assert_eq!(i2c(AddressPin::Scl, AddressPin::Sda), 0x53);
+        assert_eq!(i2c(AddressPin::Sda, AddressPin::Sda), 0x57);
+    }
+
+    #[test]
+    fn test_i2c_driver() {
+        let spi = MockSpi::new();
+        let bus = I2cInterface::new(
+            MockI2c {
+                spi: spi.clone(),
+                address: 0x4E,
+            },
+            AddressPin::Vss,
+            AddressPin::Scl,
+        );
+        let mut uart = Sc16is752::new(bus, MockIrq, Channel::B);
+
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, LCR), 0x03);
+
+        spi.set(Channel::B, Window::General, TXLVL, 64);
+        block_on(uart.write_all(b"over i2c")).unwrap();
+        assert_eq!(spi.writes(Channel::B, Window::General, THR), b"over i2c");
+
+        spi.set(Channel::B, Window::General, RXLVL, 3);
+        spi.queue(Channel::B, Window::General, RHR, b"ack");
+        let mut buf = [0; 8];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ack");
+    }
+}
@@ -1,196 +1,4936 @@
 #![no_std]
 
+use core::future::{Future, poll_fn};
+use core::pin::{Pin, pin};
+use core::task::Poll;
+use core::time::Duration;
+
+use embedded_hal::digital;
 use embedded_hal_async::{
+    delay::DelayNs,
     digital::Wait,
-    spi::{Error as SpiError, SpiDevice},
+    spi::{MODE_0, Mode},
 };
-use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use embedded_io_async::{BufRead, ErrorKind, ErrorType, Read, Write};
+use heapless::Vec;
 
-pub use crate::low_level::Channel;
-use crate::low_level::{FifoControl, Ier, RegisterWrapper};
+pub use crate::batch::Batch;
+pub use crate::buffered::{BufferedRx, BufferedSc16is752, BufferedState, BufferedTx};
+pub use crate::dual::{ChannelService, Sc16is752Dual, ServiceReport};
+pub use crate::gpio::{GpioInput, GpioPin, GpioPort, PinDir};
+pub use crate::halves::{RxHalf, TxHalf};
+pub use crate::interface::{AddressPin, I2cInterface, RegisterInterface};
+pub use crate::low_level::{
+    Channel, DataBits, GpioDir, InterruptSource, Parity, RxFifoTrigger, StopBits, TxFifoTrigger,
+};
+use crate::low_level::{
+    Divisor, FifoControl, Ier, IoControl, LineControl, Lsr, Msr, RegisterWrapper, SwFlow, Tcr, Tlr,
+};
+#[cfg(feature = "embassy")]
+pub use crate::piped::PipedSc16is752;
+pub use crate::polled::NoIrq;
+pub use crate::shared_irq::{IrqDispatcher, SharedIrq};
 
+mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod buffered;
+mod dual;
+mod gpio;
+mod halves;
+mod interface;
 mod low_level;
+#[cfg(feature = "embassy")]
+mod piped;
+mod polled;
+mod shared_irq;
+
+/// Register addresses and bitfield types, for external tools (host-side
+/// simulators, protocol analyzers) that build or decode the same SPI frames
+/// as the driver.
+///
+/// Some addresses alias each other and are selected by LCR: DLL/DLH are
+/// visible while `LCR[7]` is set, the enhanced registers (EFR, Xon/Xoff)
+/// while LCR is [`LCR_ENHANCED`](registers::LCR_ENHANCED).
+///
+/// ```
+/// use sc16is752_async::Channel;
+/// use sc16is752_async::registers::{Rab, ReadWrite, TXLVL};
+///
+/// // Read TXLVL of channel B
+/// let [rab] = Rab::new()
+///     .with_rw(ReadWrite::Read)
+///     .with_register(TXLVL)
+///     .with_channel(Channel::B)
+///     .into_bytes();
+/// assert_eq!(rab, 0xC2);
+/// ```
+pub mod registers {
+    pub use crate::low_level::{
+        DLH, DLL, EFCR, EFR, FCR, IER, IIR, IOCONTROL, IODIR, IOINTENA, IOSTATE, LCR, LCR_ENHANCED,
+        LSR, MAX_DUMMY_BYTES, MCR, MSR, RHR, RXLVL, SPR, TCR, THR, TLR, TXLVL, XOFF1, XOFF2, XON1,
+    };
+    pub use crate::low_level::{
+        Divisor, Efcr, Efr, FifoControl, GpioDir, Ier, Iir, IoControl, LineControl, Lsr,
+        ModemControl, Msr, Rab, ReadWrite, RxFifoTrigger, SwFlow, Tcr, Tlr, TxFifoTrigger,
+    };
+}
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 
-pub struct Sc16is752<Spi, Irq> {
+/// Driver for one channel of the chip.
+///
+/// `BUF` bounds the bytes the driver buffers internally (e.g. data read past
+/// the end of a [`Sc16is752::read_until`] frame); helpers that would need
+/// more fail with [`Error::BufferFull`].
+pub struct Sc16is752<Spi, Irq, const BUF: usize = 64> {
     regs: RegisterWrapper<Spi>,
     irq: Irq,
     channel: Channel,
+    /// Bytes already taken out of the RX FIFO but not yet handed to the
+    /// caller.
+    stash: Vec<u8, BUF>,
+    rx_error_policy: RxErrorPolicy,
+    overrun_is_error: bool,
+    error_replacement: Option<u8>,
+    /// Set when a byte with a parity or framing error was delivered.
+    rx_error: bool,
+    baud_rate: u32,
+    /// The crystal frequency last given to init, 0 before that.
+    crystal_freq: u32,
+    line_config: LineConfig,
+    /// FCR is write-only, so the last value written (minus the self-clearing
+    /// reset bits) is kept here.
+    fcr: FifoControl,
+    /// GPIOs whose logical level is the inverse of the electrical one.
+    gpio_active_low: u8,
+    spi_mode: Mode,
+    /// Error bits of every LSR read since [`Sc16is752::take_accumulated_errors`].
+    lsr_errors: u8,
+    /// Whether init runs [`Sc16is752::self_test`] first.
+    self_test_on_init: bool,
+    variant: Variant,
+    /// Set by [`Sc16is752::enter_sleep`] until the sleep enable is cleared.
+    asleep: bool,
+    /// A receive error hit by a read that had already taken good bytes,
+    /// reported by the next read instead.
+    deferred_rx_error: Option<DeferredRxError>,
 }
 
 impl<Spi, Irq> Sc16is752<Spi, Irq>
 where
-    Spi: SpiDevice,
+    Spi: RegisterInterface,
     Irq: Wait,
 {
+    /// Creates a driver with the default 64 bytes of internal buffering.
     pub fn new(spi: Spi, irq: Irq, channel: Channel) -> Self {
+        Self::with_capacity(spi, irq, channel)
+    }
+
+    /// Creates a driver for another member of the family, on its only
+    /// channel for single-channel parts (channel A on dual ones).
+    pub fn for_variant(spi: Spi, irq: Irq, variant: Variant) -> Self {
+        let mut uart = Self::with_capacity(spi, irq, Channel::A);
+        uart.variant = variant;
+        uart
+    }
+
+    /// Creates independent drivers for channel A and channel B, so that
+    /// both can be used concurrently, e.g. a modem on A and a console on B.
+    ///
+    /// Both share the SPI device, which must be cloneable (typically a
+    /// handle on a bus shared behind a mutex, which serializes their
+    /// accesses). Boards that wire both channels to one IRQ pin should pass
+    /// two handles from an [`IrqDispatcher`]: with plain clones of the pin,
+    /// the idle channel would keep checking its IIR on a line held low by
+    /// the other without ever yielding to the task that services it.
+    pub fn split<IrqB: Wait>(spi: Spi, irq_a: Irq, irq_b: IrqB) -> (Self, Sc16is752<Spi, IrqB>)
+    where
+        Spi: Clone,
+    {
+        (
+            Sc16is752::new(spi.clone(), irq_a, Channel::A),
+            Sc16is752::new(spi, irq_b, Channel::B),
+        )
+    }
+}
+
+impl<Spi, Irq, const BUF: usize> Sc16is752<Spi, Irq, BUF>
+where
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    /// Creates a driver buffering at most `BUF` bytes internally, e.g.
+    /// `Sc16is752::<_, _, 16>::with_capacity(spi, irq, Channel::A)`.
+    pub fn with_capacity(spi: Spi, irq: Irq, channel: Channel) -> Self {
         Sc16is752 {
             regs: RegisterWrapper::new(spi),
             irq,
             channel,
+            stash: Vec::new(),
+            rx_error_policy: RxErrorPolicy::Deliver,
+            overrun_is_error: false,
+            error_replacement: None,
+            rx_error: false,
+            baud_rate: 0,
+            crystal_freq: 0,
+            line_config: LineConfig::default(),
+            fcr: FifoControl::new(),
+            gpio_active_low: 0,
+            spi_mode: MODE_0,
+            lsr_errors: 0,
+            self_test_on_init: false,
+            variant: Variant::Sc16is752,
+            asleep: false,
+            deferred_rx_error: None,
+        }
+    }
+
+    /// Tells the driver which member of the family it drives (the
+    /// SC16IS752 by default), so that features the chip lacks fail with
+    /// [`Error::Unsupported`] instead of writing reserved bits. Fails the
+    /// same way for channel B of a single-channel part.
+    pub fn set_variant(&mut self, variant: Variant) -> Result<(), Error<Spi::Error>> {
+        if self.channel == Channel::B && variant.channels() < 2 {
+            return Err(Error::Unsupported);
+        }
+        self.variant = variant;
+        Ok(())
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    fn check_gpio(&self) -> Result<(), Error<Spi::Error>> {
+        if !self.variant.has_gpio() {
+            return Err(Error::Unsupported);
         }
+        Ok(())
+    }
+
+    /// Sets how many dummy bytes to clock between the command byte and the
+    /// data of a read, for SPI setups that need extra turnaround. The
+    /// default is none; at most [`MAX_DUMMY_BYTES`](registers::MAX_DUMMY_BYTES)
+    /// are used.
+    pub fn set_spi_dummy_bytes(&mut self, count: u8) {
+        self.regs.set_dummy_bytes(count);
+    }
+
+    /// Keeps a write-through copy of LCR, MCR, IER, EFR and IODIR, so that
+    /// read-modify-writes of them (e.g. opening the divisor latch or
+    /// flipping an MCR bit) only read from the chip the first time. Off by
+    /// default.
+    ///
+    /// The cache only sees this driver's accesses: with a driver per
+    /// channel, at most one of them should touch the GPIOs, and anything
+    /// else changing the chip behind the driver's back (a hardware reset,
+    /// another MCU) calls for [`Self::invalidate_register_cache`]. A
+    /// software [`reset`](Self::reset) clears it by itself.
+    pub fn set_register_cache(&mut self, enabled: bool) {
+        self.regs.set_cache_enabled(enabled);
+    }
+
+    /// Makes the next access to each cached register read it from the chip.
+    pub fn invalidate_register_cache(&mut self) {
+        self.regs.invalidate_cache();
     }
 
+    /// Initializes the channel for 8N1 at `baud_rate`.
     pub async fn init(
         &mut self,
         baud_rate: u32,
         crystal_freq: u32,
     ) -> Result<(), Error<Spi::Error>> {
-        // Enable FIFO with reset of both TX and RX
+        self.init_with_config(baud_rate, crystal_freq, LineConfig::default())
+            .await
+    }
+
+    /// Initializes the channel as described by `config`, returning the baud
+    /// rate actually achieved. See [`Self::init_with_prescaler`].
+    pub async fn init_from_config(&mut self, config: Config) -> Result<u32, Error<Spi::Error>> {
+        self.init_with_prescaler(
+            config.baud_rate,
+            config.crystal_freq,
+            config.prescaler,
+            config.line,
+        )
+        .await
+    }
+
+    /// Initializes the channel with the given framing and no prescaler. See
+    /// [`Self::init_with_prescaler`].
+    pub async fn init_with_config(
+        &mut self,
+        baud_rate: u32,
+        crystal_freq: u32,
+        line_config: LineConfig,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.init_with_prescaler(baud_rate, crystal_freq, Prescaler::DivideByOne, line_config)
+            .await
+            .map(drop)
+    }
+
+    /// Initializes the channel with the given framing and clock prescaler,
+    /// returning the baud rate actually achieved with the nearest divisor.
+    ///
+    /// Fails with [`Error::UnsupportedBaudRate`], before touching the chip,
+    /// if that divisor is 0 or does not fit in DLL/DLH. How far the result
+    /// is off `baud_rate` is left for the caller to judge.
+    pub async fn init_with_prescaler(
+        &mut self,
+        baud_rate: u32,
+        crystal_freq: u32,
+        prescaler: Prescaler,
+        line_config: LineConfig,
+    ) -> Result<u32, Error<Spi::Error>> {
+        let divisor = checked_divisor(baud_rate, crystal_freq, prescaler)?;
+
+        if self.self_test_on_init {
+            self.self_test().await?;
+        }
+
+        // A window left open, e.g. by an earlier run that was interrupted,
+        // would send FCR to EFR and the MCR read to XON1
         self.regs
-            .write_fcr(
-                self.channel,
-                FifoControl::new()
-                    .with_enable(true)
-                    .with_reset_tx(true)
-                    .with_reset_rx(true),
-            )
+            .write_lcr(self.channel, line_config.line_control())
             .await?;
 
-        // Read current LCR to preserve settings
-        let mut lcr_val = self.regs.read(low_level::LCR, self.channel).await?[0];
+        // Enable FIFO with reset of both TX and RX
+        self.write_fcr(
+            FifoControl::new()
+                .with_enable(true)
+                .with_reset_tx(true)
+                .with_reset_rx(true),
+        )
+        .await?;
+
+        // Changing MCR[7] takes a detour through EFR, so only do it if needed
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        if mcr.divisor() != prescaler.into() {
+            self.set_prescaler(prescaler).await?;
+        }
+        // Framing, e.g. 8N1, goes in with the LCR write closing the latch
+        self.write_divisor_and_line_config(divisor, line_config)
+            .await?;
 
-        // Enable divisor latch (set bit 7)
-        lcr_val |= 0x80;
+        // Enable RHR interrupt so we get notified when data arrives
         self.regs
-            .write(low_level::LCR, self.channel, [lcr_val])
+            .write_ier(self.channel, Ier::new().with_receive_holding_register(true))
             .await?;
 
-        // Check MCR register to determine prescaler
-        let mcr = self.regs.read(low_level::MCR, self.channel).await?[0];
-        let prescaler = if mcr == 0 { 1 } else { 4 };
+        let actual = actual_baud(crystal_freq, prescaler, divisor);
+        self.baud_rate = actual;
+        self.crystal_freq = crystal_freq;
+
+        Ok(actual)
+    }
+
+    /// Re-syncs the driver with a chip that kept its configuration while the
+    /// MCU was in deep sleep, instead of running [`Self::init`] again.
+    ///
+    /// The framing is read back from LCR, leaving the divisor latch if it
+    /// was left selected. An enhanced window left open (LCR=0xBF) holds no
+    /// framing, so it is closed with the framing this driver last set. The
+    /// FIFO enable in the FCR shadow is corrected from its IIR mirror, but
+    /// FCR itself is not rewritten, as its trigger levels can't be read
+    /// back. Stale RX data and line errors are discarded and the RX
+    /// interrupt is re-armed on top of the other enables in IER. The baud
+    /// rate is not read back, as that needs the crystal frequency.
+    pub async fn resume_from_sleep(&mut self) -> Result<(), Error<Spi::Error>> {
+        let lcr = self.regs.read_lcr(self.channel).await?;
+        if lcr.into_bytes() == [low_level::LCR_ENHANCED] {
+            // Just clearing LCR[7] would leave 0x3F: 8 data bits, 2 stop bits
+            // and stick parity
+            self.regs
+                .write_lcr(self.channel, self.line_config.line_control())
+                .await?;
+        } else {
+            if lcr.divisor_latch_enable() {
+                self.regs
+                    .write_lcr(self.channel, lcr.with_divisor_latch_enable(false))
+                    .await?;
+            }
+            self.line_config = LineConfig {
+                data_bits: lcr.data_bits(),
+                stop_bits: lcr.stop_bits(),
+                // Without LCR[3] there is no parity, whatever LCR[5:4] say
+                parity: lcr.parity_or_err().unwrap_or(Parity::None),
+            };
+        }
+
+        let iir = self.regs.read_iir(self.channel).await?;
+        self.fcr = self.fcr.with_enable(iir.fifos_enabled() != 0);
+
+        self.stash.clear();
+        self.rx_error = false;
+        self.deferred_rx_error = None;
+        if self.fcr.enable() {
+            // Drained rather than reset through FCR, which would also
+            // overwrite the trigger levels with the shadow's
+            let level = self.rx_level().await?;
+            if level > 0 {
+                let mut stale = [0; 64];
+                self.regs
+                    .read_many_rhr(self.channel, &mut stale[..level.min(64)])
+                    .await?;
+            }
+        } else {
+            self.regs.read(low_level::RHR, self.channel).await?;
+        }
+        self.read_lsr().await?;
 
-        // Calculate and write divisor
-        let divisor = ((crystal_freq / prescaler) / (16 * baud_rate)) as u16;
-        let [msb, lsb] = divisor.to_be_bytes();
+        let ier = self.regs.read_ier(self.channel).await?;
+        self.regs
+            .write_ier(self.channel, ier.with_receive_holding_register(true))
+            .await
+    }
 
-        self.regs.write(low_level::DLL, self.channel, [lsb]).await?;
-        self.regs.write(low_level::DLH, self.channel, [msb]).await?;
+    /// Selects the clock prescaler (`MCR[7]`). Enhanced functions (`EFR[4]`) are
+    /// enabled on the way, as `MCR[7]` cannot be changed otherwise.
+    pub async fn set_prescaler(&mut self, prescaler: Prescaler) -> Result<(), Error<Spi::Error>> {
+        self.enable_enhanced_functions().await?;
 
-        // Configure line control: 8N1 (8 data bits, no parity, 1 stop bit)
+        let mcr = self.regs.read_mcr(self.channel).await?;
         self.regs
-            .write(low_level::LCR, self.channel, [0x03])
-            .await?;
+            .write_mcr(self.channel, mcr.with_divisor(prescaler.into()))
+            .await
+    }
 
-        // Enable RHR interrupt so we get notified when data arrives
+    /// Sets `baud_rate` using whichever prescaler gets closer to it with the
+    /// given crystal, returning the baud rate actually achieved.
+    ///
+    /// Fails with [`Error::UnsupportedBaudRate`], before touching the chip,
+    /// if neither prescaler gives a divisor that fits in DLL/DLH.
+    pub async fn set_baud_optimal(
+        &mut self,
+        baud_rate: u32,
+        crystal_freq: u32,
+    ) -> Result<u32, Error<Spi::Error>> {
+        let (prescaler, divisor) = [Prescaler::DivideByOne, Prescaler::DivideByFour]
+            .into_iter()
+            .filter_map(|prescaler| {
+                let divisor = checked_divisor::<Spi::Error>(baud_rate, crystal_freq, prescaler);
+                Some((prescaler, divisor.ok()?))
+            })
+            .min_by_key(|&(prescaler, divisor)| {
+                actual_baud(crystal_freq, prescaler, divisor).abs_diff(baud_rate)
+            })
+            .ok_or(Error::UnsupportedBaudRate)?;
+
+        // Changing MCR[7] takes a detour through EFR, so only do it if needed
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        if mcr.divisor() != prescaler.into() {
+            self.set_prescaler(prescaler).await?;
+        }
+        self.regs.write_divisor(self.channel, divisor).await?;
+
+        let actual = actual_baud(crystal_freq, prescaler, divisor);
+        self.baud_rate = actual;
+        Ok(actual)
+    }
+
+    /// Changes the baud rate after [`Self::init`], with the crystal frequency
+    /// given there, and returns the baud rate actually achieved. Whichever
+    /// prescaler gets closer is used, preferring none.
+    ///
+    /// Fails with [`Error::UnsupportedBaudRate`] if no divisor fits and
+    /// [`Error::NotInitialized`] before init. How much error is acceptable
+    /// is up to the caller, see [`baud_error_permille`].
+    pub async fn set_baud_rate(&mut self, baud_rate: u32) -> Result<u32, Error<Spi::Error>> {
+        if self.crystal_freq == 0 {
+            return Err(Error::NotInitialized);
+        }
+
+        self.set_baud_optimal(baud_rate, self.crystal_freq).await
+    }
+
+    /// Changes the framing without touching the baud rate or FIFOs.
+    pub async fn set_line_config(&mut self, config: LineConfig) -> Result<(), Error<Spi::Error>> {
         self.regs
-            .write_ier(self.channel, Ier::new().with_receive_holding_register(true))
+            .write_lcr(self.channel, config.line_control())
             .await?;
-
+        self.line_config = config;
         Ok(())
     }
 
-    async fn wait_for_irq(&mut self) -> Result<(), Error<Spi::Error>> {
-        self.irq.wait_for_low().await.ok();
+    /// Writes the divisor and then the framing with the same LCR write that
+    /// closes the divisor latch, without reading LCR first.
+    async fn write_divisor_and_line_config(
+        &mut self,
+        divisor: u16,
+        config: LineConfig,
+    ) -> Result<(), Error<Spi::Error>> {
+        let [lcr] = config.line_control().into_bytes();
+        self.regs
+            .write_divisor_with_lcr(self.channel, divisor, lcr)
+            .await?;
+        self.line_config = config;
         Ok(())
     }
-}
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error<SpiErr> {
-    #[error("spi error: {0:?}")]
-    Spi(SpiErr),
-}
+    /// Returns the baud rate and framing last configured through this
+    /// driver, without reading anything back from the chip.
+    pub fn current_config(&self) -> (u32, LineConfig) {
+        (self.baud_rate, self.line_config)
+    }
 
-impl<SpiErr: SpiError> embedded_io_async::Error for Error<SpiErr> {
-    fn kind(&self) -> embedded_io_async::ErrorKind {
-        ErrorKind::Other
+    /// Sets the FIFO trigger levels and interrupt enables for a
+    /// [`StreamProfile`].
+    ///
+    /// The FIFOs are not reset. The TX trigger level only takes effect while
+    /// enhanced functions (`EFR[4]`) are enabled; otherwise the chip keeps using
+    /// its default.
+    pub async fn set_streaming_profile(
+        &mut self,
+        profile: StreamProfile,
+    ) -> Result<(), Error<Spi::Error>> {
+        let (fcr, ier) = profile.registers();
+        self.write_fcr(fcr).await?;
+        self.regs.write_ier(self.channel, ier).await
     }
-}
 
-impl<Spi, Irq> ErrorType for Sc16is752<Spi, Irq>
-where
-    Spi: embedded_hal_async::spi::ErrorType,
-{
-    type Error = Error<Spi::Error>;
-}
+    /// Enables automatic Xon/Xoff flow control using the Xon1/Xoff1
+    /// characters.
+    ///
+    /// With `thresholds`, TCR is programmed so that the chip sends Xoff once
+    /// the RX FIFO reaches the halt level and Xon once it drains to the
+    /// resume level, rather than flapping around a single level.
+    pub async fn enable_software_flow_control(
+        &mut self,
+        xon: u8,
+        xoff: u8,
+        mode: SwFlowMode,
+        thresholds: Option<FlowThresholds>,
+    ) -> Result<(), Error<Spi::Error>> {
+        let batch = self.batch().software_flow_control(xon, xoff, mode);
 
-impl<Spi: SpiDevice, Irq: Wait> Write for Sc16is752<Spi, Irq> {
-    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        if buf.is_empty() {
-            return Ok(0);
+        match thresholds {
+            Some(thresholds) => batch.flow_thresholds(thresholds).commit().await,
+            None => batch.commit().await,
         }
+    }
 
-        loop {
-            let space = self.regs.read_txlvl(self.channel).await? as usize;
+    /// Selects the flow control of the channel, typically right after
+    /// [`Self::init`], turning off whatever kind was enabled before. Both
+    /// kinds get the [default](FlowThresholds::default) TCR levels.
+    pub async fn set_flow_control(&mut self, flow: FlowControl) -> Result<(), Error<Spi::Error>> {
+        let batch = self.batch();
+        match flow {
+            FlowControl::None => batch
+                .hardware_flow_control(false, false)
+                .disable_software_flow_control(),
+            FlowControl::RtsCts => batch
+                .hardware_flow_control(true, true)
+                .disable_software_flow_control()
+                .flow_thresholds(FlowThresholds::default()),
+            FlowControl::XonXoff { xon, xoff } => batch
+                .hardware_flow_control(false, false)
+                .software_flow_control(xon, xoff, SwFlowMode::Both)
+                .flow_thresholds(FlowThresholds::default()),
+        }
+        .commit()
+        .await
+    }
 
-            if space > 0 {
-                let len = buf.len().min(space);
-                self.regs.write_many_thr(self.channel, &buf[..len]).await?;
-                return Ok(len);
-            }
+    /// Enables automatic RTS and CTS flow control, keeping the current TCR
+    /// levels. LCR is switched to the enhanced register set to reach EFR and
+    /// restored afterwards, so the line settings are left as they were.
+    pub async fn enable_hardware_flow_control(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.batch()
+            .hardware_flow_control(true, true)
+            .commit()
+            .await
+    }
 
-            // No space — enable THR interrupt and wait
-            self.regs
-                .write_ier(
-                    self.channel,
-                    Ier::new()
-                        .with_receive_holding_register(true)
-                        .with_transmit_holding_register(true),
-                )
-                .await?;
+    /// Enables automatic Xon/Xoff flow control in the given direction(s),
+    /// with the [default](FlowThresholds::default) TCR levels so that Xoff
+    /// goes out while the RX FIFO still has room for what the peer sends
+    /// before it reacts.
+    pub async fn configure_software_flow_control(
+        &mut self,
+        xon: u8,
+        xoff: u8,
+        mode: SwFlowMode,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.enable_software_flow_control(xon, xoff, mode, Some(FlowThresholds::default()))
+            .await
+    }
 
-            self.wait_for_irq().await?;
+    /// Sets up automatic RTS/CTS flow control together with the TCR levels
+    /// at which RTS is deasserted and asserted again.
+    pub async fn configure_hardware_flow(
+        &mut self,
+        cfg: HwFlowConfig,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.batch()
+            .hardware_flow_control(cfg.auto_rts, cfg.auto_cts)
+            .flow_thresholds(FlowThresholds {
+                halt: cfg.halt_chars,
+                resume: cfg.resume_chars,
+            })
+            .commit()
+            .await
+    }
 
-            // Read IIR to clear the interrupt
-            let _iir = self.regs.read_iir(self.channel).await?;
+    /// Returns whether the chip handles RTS/CTS flow control by itself, i.e.
+    /// whether auto-RTS or auto-CTS (or both) is enabled in EFR.
+    pub async fn hardware_flow_active(&mut self) -> Result<bool, Error<Spi::Error>> {
+        let channel = self.channel;
+        let efr = self
+            .regs
+            .with_enhanced(channel, async |regs| regs.read_efr(channel).await)
+            .await?;
 
-            // Disable THR interrupt (leave RHR enabled)
-            self.regs
-                .write_ier(self.channel, Ier::new().with_receive_holding_register(true))
-                .await?;
+        Ok(efr.auto_rts() || efr.auto_cts())
+    }
+
+    /// Enables or disables internal loopback (`MCR[4]`): TX is fed straight
+    /// back into RX and the TX pin idles high, so the SPI to UART path can
+    /// be tested with nothing connected. The rest of MCR is left alone.
+    pub async fn set_loopback(&mut self, enabled: bool) -> Result<(), Error<Spi::Error>> {
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_loopback(enabled))
+            .await
+    }
+
+    /// Lets the transmitter drive RTS as the direction control of an RS-485
+    /// transceiver: RTS is asserted while data is being sent and released
+    /// once the shift register is empty. By default asserted means low, as
+    /// for RTS everywhere else; `invert_rts` drives it high instead, for
+    /// transceivers whose driver enable is active-high.
+    ///
+    /// Since RTS drops with the last stop bit, [`flush`](Write::flush)
+    /// returning also means the bus has been released.
+    pub async fn enable_rs485(&mut self, invert_rts: bool) -> Result<(), Error<Spi::Error>> {
+        let efcr = self.regs.read_efcr(self.channel).await?;
+        self.regs
+            .write_efcr(
+                self.channel,
+                efcr.with_rts_control(true).with_rts_invert(invert_rts),
+            )
+            .await
+    }
+
+    /// Undoes [`Self::enable_rs485`], handing RTS back to MCR and the
+    /// hardware flow control.
+    pub async fn disable_rs485(&mut self) -> Result<(), Error<Spi::Error>> {
+        let efcr = self.regs.read_efcr(self.channel).await?;
+        self.regs
+            .write_efcr(
+                self.channel,
+                efcr.with_rts_control(false).with_rts_invert(false),
+            )
+            .await
+    }
+
+    /// Switches the channel to IrDA SIR encoding (`MCR[6]`) for driving an IR
+    /// transceiver, with the pulse width selected by `pulse` (`EFCR[7]`).
+    pub async fn enable_irda(&mut self, pulse: IrdaPulse) -> Result<(), Error<Spi::Error>> {
+        if pulse == IrdaPulse::Quarter && !self.variant.has_fast_irda() {
+            return Err(Error::Unsupported);
+        }
+        self.enable_enhanced_functions().await?;
+
+        let efcr = self.regs.read_efcr(self.channel).await?;
+        self.regs
+            .write_efcr(
+                self.channel,
+                efcr.with_irda_fast(pulse == IrdaPulse::Quarter),
+            )
+            .await?;
+
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs.write_mcr(self.channel, mcr.with_irda(true)).await
+    }
+
+    /// Goes back to plain UART encoding after [`Self::enable_irda`].
+    pub async fn disable_irda(&mut self) -> Result<(), Error<Spi::Error>> {
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_irda(false))
+            .await
+    }
+
+    /// Hands the channel's four GPIOs (`GPIO[7:4]` for A, `GPIO[3:0]` for B)
+    /// over to DTR, DSR, CD and RI. Until then only RTS and CTS are wired
+    /// up, and the other MSR bits read as deasserted.
+    pub async fn enable_modem_pins(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.check_gpio()?;
+        let ioc = self.regs.read_iocontrol().await?;
+        let ioc = match self.channel {
+            Channel::A => ioc.with_modem_pins_a(true),
+            Channel::B => ioc.with_modem_pins_b(true),
+        };
+        self.regs.write_iocontrol(ioc).await
+    }
+
+    /// Drives RTS manually (`MCR[1]`); `true` asserts it, i.e. pulls it low.
+    /// Has no visible effect while auto RTS or RS-485 mode controls the pin.
+    pub async fn set_rts(&mut self, asserted: bool) -> Result<(), Error<Spi::Error>> {
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_rts(asserted))
+            .await
+    }
+
+    /// Drives DTR (`MCR[0]`); `true` asserts it. Needs
+    /// [`Self::enable_modem_pins`].
+    pub async fn set_dtr(&mut self, asserted: bool) -> Result<(), Error<Spi::Error>> {
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_dtr(asserted))
+            .await
+    }
+
+    /// Reads the modem inputs. This clears the modem status interrupt and
+    /// the change bits, which are returned along with the levels.
+    pub async fn modem_status(&mut self) -> Result<ModemStatus, Error<Spi::Error>> {
+        Ok(self.regs.read_msr(self.channel).await?.into())
+    }
+
+    /// Waits for any modem input to change, returning the new status.
+    pub async fn wait_for_modem_change(&mut self) -> Result<ModemStatus, Error<Spi::Error>> {
+        self.wait_for_modem_status(|status| status.changed()).await
+    }
+
+    /// Waits until CTS is asserted, returning right away if it already is.
+    pub async fn wait_for_cts(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.wait_for_modem_status(|status| status.cts)
+            .await
+            .map(drop)
+    }
+
+    /// Enables the modem status interrupt alone and waits until `done`
+    /// holds for the status, checking once upfront. IER is restored
+    /// afterwards.
+    ///
+    /// The other sources are masked while waiting: RX data outranks modem
+    /// status in IIR, so unread bytes would otherwise hold the IRQ line low
+    /// without IIR ever reporting the modem change. Bytes received in the
+    /// meantime stay in the FIFO.
+    async fn wait_for_modem_status(
+        &mut self,
+        mut done: impl FnMut(&ModemStatus) -> bool,
+    ) -> Result<ModemStatus, Error<Spi::Error>> {
+        let status = self.modem_status().await?;
+        if done(&status) {
+            return Ok(status);
+        }
+
+        let ier = self.regs.read_ier(self.channel).await?;
+        self.regs
+            .write_ier(self.channel, Ier::new().with_modem_status(true))
+            .await?;
+
+        let result = async {
+            loop {
+                self.wait_for_irq().await?;
+                if self.poll_interrupt().await? != Some(InterruptSource::ModemInterrupt) {
+                    // The line is held by something else, e.g. the other
+                    // channel, so let its driver run
+                    yield_now().await;
+                    continue;
+                }
+
+                let status = self.modem_status().await?;
+                if done(&status) {
+                    break Ok(status);
+                }
+            }
         }
+        .await;
+
+        self.regs.write_ier(self.channel, ier).await?;
+        result
     }
 
-    async fn flush(&mut self) -> Result<(), Self::Error> {
+    /// Puts the channel in 9-bit (multidrop) mode (`EFCR[0]`), where the
+    /// parity bit tells address bytes from data. Parity is set to
+    /// [`Parity::Space`] so that [`Write`] sends data bytes; addresses go
+    /// out through [`Self::write_address`].
+    ///
+    /// With `address` given, the chip compares received addresses against
+    /// it (XOFF2, `EFR[5]`) and drops data sent to other stations. Otherwise
+    /// everything is received, with [`Self::read_multidrop`] telling
+    /// addresses and data apart.
+    pub async fn enable_multidrop(&mut self, address: Option<u8>) -> Result<(), Error<Spi::Error>> {
+        let channel = self.channel;
+        self.regs
+            .with_enhanced(channel, async |regs| {
+                if let Some(address) = address {
+                    regs.write(low_level::XOFF2, channel, [address]).await?;
+                }
+                let efr = regs.read_efr(channel).await?;
+                regs.write_efr(channel, efr.with_special_char_detect(address.is_some()))
+                    .await
+            })
+            .await?;
+
+        self.set_line_config(LineConfig {
+            parity: Parity::Space,
+            ..self.line_config
+        })
+        .await?;
+
+        let efcr = self.regs.read_efcr(channel).await?;
+        self.regs
+            .write_efcr(channel, efcr.with_multidrop(true))
+            .await
+    }
+
+    /// Sends `address` with the 9th bit set, in multidrop mode. The parity
+    /// setting applies to whatever is being shifted out, so this flushes
+    /// before and after switching it.
+    pub async fn write_address(&mut self, address: u8) -> Result<(), Error<Spi::Error>> {
+        let config = self.line_config;
+        self.flush().await?;
+        self.set_line_config(LineConfig {
+            parity: Parity::Mark,
+            ..config
+        })
+        .await?;
+        self.write_all(&[address]).await?;
+        self.flush().await?;
+        self.set_line_config(config).await
+    }
+
+    /// Waits for the next byte in multidrop mode. The chip reports a set
+    /// 9th bit as a parity error, which marks an address byte; the
+    /// [`RxErrorPolicy`] does not apply here.
+    ///
+    /// Bytes already taken from the FIFO by [`Read`] have lost that
+    /// distinction, so don't mix the two.
+    pub async fn read_multidrop(&mut self) -> Result<MultidropByte, Error<Spi::Error>> {
+        let mut armed = false;
         loop {
             let lsr = self.regs.read_lsr(self.channel).await?;
-            if lsr.thr_empty() && lsr.thr_tsr_empty() {
-                return Ok(());
-            }
+            let address = lsr.data_in_receiver() && lsr.parity_error();
+            // An address's 9th bit is no line error, nor does it make the
+            // FIFO hold bad data
+            self.accumulate_line_errors(if address {
+                lsr.with_parity_error(false).with_fifo_data_error(false)
+            } else {
+                lsr
+            });
 
-            // Enable THR interrupt and wait for FIFO to drain
-            self.regs
-                .write_ier(
-                    self.channel,
-                    Ier::new()
-                        .with_receive_holding_register(true)
-                        .with_transmit_holding_register(true),
-                )
-                .await?;
+            if lsr.data_in_receiver() {
+                let [byte] = self.regs.read(low_level::RHR, self.channel).await?;
+                return Ok(if address {
+                    MultidropByte::Address(byte)
+                } else {
+                    MultidropByte::Data(byte)
+                });
+            }
 
+            if !armed {
+                self.enable_rx_interrupt().await?;
+                armed = true;
+            }
             self.wait_for_irq().await?;
-            let _iir = self.regs.read_iir(self.channel).await?;
+            self.poll_interrupt().await?;
+        }
+    }
 
-            // Disable THR interrupt
-            self.regs
-                .write_ier(self.channel, Ier::new().with_receive_holding_register(true))
-                .await?;
+    /// Returns the chip's eight GPIO pins as a [`GpioPort`]. The pins are
+    /// shared by both channels.
+    pub fn gpio_port(&mut self) -> GpioPort<'_, Spi> {
+        GpioPort::new(
+            &mut self.regs,
+            self.gpio_active_low,
+            self.variant.has_gpio(),
+        )
+    }
+
+    /// Borrows GPIO`pin` as an input that implements
+    /// [`Wait`] through the input change
+    /// interrupt, sharing this driver's IRQ pin.
+    pub fn gpio_input(&mut self, pin: u8) -> Result<GpioInput<'_, Spi, Irq>, Error<Spi::Error>> {
+        self.check_gpio()?;
+        let active_low = self.gpio_active_low & gpio::pin_mask(pin)? != 0;
+        GpioInput::new(&mut self.regs, &mut self.irq, pin, active_low)
+    }
+
+    /// Marks the GPIOs in `mask` (bit `n` is GPIO`n`) as active-low: from
+    /// then on the GPIO methods take and return logical levels, so `true`
+    /// drives those pins low.
+    pub fn set_gpio_active_low(&mut self, mask: u8) {
+        self.gpio_active_low = mask;
+    }
+
+    /// Configures which GPIOs are outputs. Like all GPIO methods this acts on
+    /// the pins shared by both channels, whichever channel `self` drives.
+    pub async fn set_gpio_direction(&mut self, dirs: GpioDir) -> Result<(), Error<Spi::Error>> {
+        self.check_gpio()?;
+        let [dirs] = dirs.into_bytes();
+        self.regs.write_iodir(dirs).await
+    }
+
+    /// Sets the logical levels of all output GPIOs at once (bit `n` is
+    /// GPIO`n`).
+    pub async fn set_gpio(&mut self, levels: u8) -> Result<(), Error<Spi::Error>> {
+        self.check_gpio()?;
+        self.regs.write_iostate(levels ^ self.gpio_active_low).await
+    }
+
+    /// Reads the logical levels of all GPIOs (bit `n` is GPIO`n`).
+    pub async fn read_gpio(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.check_gpio()?;
+        Ok(self.regs.read_iostate().await? ^ self.gpio_active_low)
+    }
+
+    /// Sets the logical level of a single GPIO, leaving the others alone.
+    pub async fn write_gpio_pin(&mut self, pin: u8, level: bool) -> Result<(), Error<Spi::Error>> {
+        self.gpio_port().write_pin(pin, level).await
+    }
+
+    /// Reads the logical level of a single GPIO.
+    pub async fn read_gpio_pin(&mut self, pin: u8) -> Result<bool, Error<Spi::Error>> {
+        self.gpio_port().read_pin(pin).await
+    }
+
+    /// Reads the GPIO input levels latched at the last change as a mask (bit
+    /// `n` is GPIO`n`) and releases the latch, so short pulses between polls
+    /// are not missed; it works the same whether or not the input interrupt
+    /// fired. Fails with [`Error::LatchDisabled`] unless
+    /// [`GpioPort::set_input_latch`] is enabled (`IOControl[0]`), as IOState
+    /// would only hold the live levels then.
+    pub async fn read_gpio_latched(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.check_gpio()?;
+        if !self.regs.read_iocontrol().await?.io_latch() {
+            return Err(Error::LatchDisabled);
         }
+
+        Ok(self.regs.read_iostate().await? ^ self.gpio_active_low)
     }
-}
 
-impl<Spi: SpiDevice, Irq: Wait> Read for Sc16is752<Spi, Irq> {
-    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        if buf.is_empty() {
-            return Ok(0);
+    /// Drives GPIO`pin` to the opposite of its current level for `duration`
+    /// and then back, e.g. to reset a device whose active-low reset line is
+    /// wired to it. The pin must already be configured as an output.
+    pub async fn pulse_gpio(
+        &mut self,
+        pin: u8,
+        duration: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        let mask = gpio::pin_mask(pin)?;
+        self.check_gpio()?;
+        if self.regs.read_iodir().await? & mask == 0 {
+            return Err(Error::NotAnOutput);
         }
 
+        let levels = self.regs.read_iostate().await?;
+        self.regs.write_iostate(levels ^ mask).await?;
+        delay_for(delay, duration).await;
+        self.regs.write_iostate(levels).await
+    }
+
+    /// Starts a [`Batch`] of configuration changes, which are applied together
+    /// with as few register accesses as possible.
+    pub fn batch(&mut self) -> Batch<'_, Spi, Irq, BUF> {
+        Batch::new(self)
+    }
+
+    /// Waits until the far end drops carrier detect (CD), i.e. hangs up.
+    ///
+    /// Only the modem status interrupt is enabled while waiting; IER is
+    /// restored afterwards, and any data received in the meantime stays in
+    /// the RX FIFO. On the SC16IS752 the CD input shares a pin with a GPIO,
+    /// so the modem pins must be enabled in IOControl.
+    pub async fn wait_for_carrier_loss(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.wait_for_modem_status(|status| status.cd_changed && !status.cd)
+            .await
+            .map(drop)
+    }
+
+    /// Reads until `terminator` has been received and returns the length of
+    /// the frame, terminator included.
+    ///
+    /// Bytes read from the FIFO past the terminator are kept for the next
+    /// read. If `buf` fills up before the terminator arrives, `on_overflow`
+    /// decides whether to fail with [`Error::BufferFull`] or to return the
+    /// full buffer and deliver the rest of the frame on the next call.
+    pub async fn read_until(
+        &mut self,
+        buf: &mut [u8],
+        terminator: u8,
+        on_overflow: OnOverflow,
+    ) -> Result<usize, Error<Spi::Error>> {
+        let mut len = 0;
+
         loop {
-            let available = self.regs.read_rxlvl(self.channel).await? as usize;
+            let available = self.stash.len().min(buf.len() - len);
+            let found = self.stash[..available]
+                .iter()
+                .position(|&byte| byte == terminator);
+            let taken = found.map_or(available, |i| i + 1);
+            buf[len..len + taken].copy_from_slice(&self.stash[..taken]);
+            self.stash.drain(..taken);
+            len += taken;
 
-            if available > 0 {
-                let len = buf.len().min(available);
-                self.regs
-                    .read_many_rhr(self.channel, &mut buf[..len])
-                    .await?;
+            if found.is_some() {
                 return Ok(len);
             }
 
+            if len == buf.len() {
+                return match on_overflow {
+                    OnOverflow::Error => Err(Error::BufferFull),
+                    OnOverflow::Buffer => Ok(len),
+                };
+            }
+
+            self.fill_stash().await?;
+        }
+    }
+
+    /// Moves whatever the RX FIFO holds into the stash, bounded by the space
+    /// left in it, waiting for data if the FIFO is empty.
+    async fn fill_stash(&mut self) -> Result<(), Error<Spi::Error>> {
+        if self.stash.is_full() {
+            return Err(Error::BufferFull);
+        }
+
+        let mut armed = false;
+        while self.stash_rx().await? == 0 {
             // No data — wait for RHR interrupt
+            if !armed {
+                self.enable_rx_interrupt().await?;
+                armed = true;
+            }
             self.wait_for_irq().await?;
 
             // Read IIR to clear the interrupt
             let _iir = self.regs.read_iir(self.channel).await?;
         }
+
+        Ok(())
+    }
+
+    /// Moves whatever fits from the RX FIFO into the stash without waiting,
+    /// returning the number of bytes moved.
+    async fn stash_rx(&mut self) -> Result<usize, Error<Spi::Error>> {
+        let mut chunk = [0u8; 64];
+        let free = self.stash.capacity() - self.stash.len();
+        let len = self.read_fifo(&mut chunk[..free.min(64)]).await?;
+
+        // Cannot fail, len is bounded by the free space
+        let _ = self.stash.extend_from_slice(&chunk[..len]);
+
+        Ok(len)
+    }
+
+    /// Puts the channel in a safe idle state: all interrupts disabled and
+    /// cleared, the FIFOs disabled and the DTR/RTS outputs deasserted.
+    pub async fn deinit(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.clear_interrupts().await?;
+        self.write_fcr(FifoControl::new()).await?;
+
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_dtr(false).with_rts(false))
+            .await
+    }
+
+    /// Waits up to `timeout` for the transmitter to go idle (`LSR[6]`), then
+    /// runs [`Self::deinit`].
+    ///
+    /// If data is still being sent when the timeout expires, the channel is
+    /// deinitialized anyway and [`Error::FlushTimeout`] is returned.
+    pub async fn shutdown(
+        &mut self,
+        timeout: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+        let mut waited = Duration::ZERO;
+        let flushed = loop {
+            if self.read_lsr().await?.thr_tsr_empty() {
+                break Ok(());
+            }
+            if waited >= timeout {
+                break Err(Error::FlushTimeout);
+            }
+
+            delay_for(delay, POLL_INTERVAL).await;
+            waited += POLL_INTERVAL;
+        };
+
+        self.deinit().await?;
+        flushed
+    }
+
+    /// Puts the chip to sleep (`IER[4]`), stopping its oscillator until there
+    /// is activity. Enhanced functions (`EFR[4]`) are enabled on the way, as
+    /// `IER[4]` cannot be set otherwise.
+    ///
+    /// Fails with [`Error::TransmitterBusy`] unless the transmitter is idle
+    /// (`LSR[6]`), as queued data would not go out. The chip wakes up by
+    /// itself on received data or a modem input change, and also on any
+    /// register access. The interrupt enables are left alone, so the byte
+    /// that woke the chip is kept in the RX FIFO and returned by the next
+    /// read.
+    ///
+    /// Reads that return data and writes clear the sleep enable, as the
+    /// oscillator restarting each time the chip dozes off between bytes
+    /// can garble the next ones. Call this again once the line is idle.
+    pub async fn enter_sleep(&mut self) -> Result<(), Error<Spi::Error>> {
+        if !self.read_lsr().await?.thr_tsr_empty() {
+            return Err(Error::TransmitterBusy);
+        }
+
+        self.enable_enhanced_functions().await?;
+        let ier = self.regs.read_ier(self.channel).await?;
+        self.regs
+            .write_ier(self.channel, ier.with_sleep(true))
+            .await?;
+        self.asleep = true;
+        Ok(())
+    }
+
+    /// Clears the sleep enable set by [`Self::enter_sleep`].
+    pub async fn wake(&mut self) -> Result<(), Error<Spi::Error>> {
+        let ier = self.regs.read_ier(self.channel).await?;
+        self.regs
+            .write_ier(self.channel, ier.with_sleep(false))
+            .await?;
+        self.asleep = false;
+        Ok(())
+    }
+
+    /// Whether [`Self::enter_sleep`] is in effect. The chip itself may be
+    /// awake, handling activity, and doze off again afterwards.
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    async fn wake_if_asleep(&mut self) -> Result<(), Error<Spi::Error>> {
+        if self.asleep {
+            self.wake().await?;
+        }
+        Ok(())
+    }
+
+    /// Enables or disables the FIFOs. With the FIFOs disabled the channel
+    /// works like a 16450, one byte at a time.
+    pub async fn set_fifo_enabled(&mut self, enabled: bool) -> Result<(), Error<Spi::Error>> {
+        self.write_fcr(self.fcr.with_enable(enabled)).await
+    }
+
+    /// Sets the FIFO levels at which the RX and TX interrupts fire, e.g. a
+    /// low RX trigger for latency or a high one for throughput. After
+    /// [`Self::init`] both are 8.
+    ///
+    /// The levels go into FCR, which is write-only, so the rest of it is
+    /// taken from the driver's shadow copy and the FIFOs are not reset. The
+    /// finer-grained TLR levels are not used. As with
+    /// [`Self::set_streaming_profile`], the TX trigger only takes effect
+    /// while enhanced functions (`EFR[4]`) are enabled.
+    pub async fn set_fifo_triggers(
+        &mut self,
+        rx: RxFifoTrigger,
+        tx: TxFifoTrigger,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write_fcr(self.fcr.with_rx_trigger(rx).with_tx_trigger(tx))
+            .await
+    }
+
+    /// Drops everything queued in the TX FIFO, keeping the FIFO and trigger
+    /// configuration. Unlike [`Write::flush`] nothing more is sent, except
+    /// that the byte already in the transmit shift register still goes out.
+    pub async fn abort_tx(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.write_fcr(self.fcr.with_reset_tx(true)).await
+    }
+
+    /// Writes FCR and updates the shadow copy.
+    async fn write_fcr(&mut self, fcr: FifoControl) -> Result<(), Error<Spi::Error>> {
+        self.regs.write_fcr(self.channel, fcr).await?;
+        self.fcr = fcr.with_reset_rx(false).with_reset_tx(false);
+        Ok(())
+    }
+
+    /// Number of bytes in the RX FIFO. RXLVL is meaningless with the FIFO
+    /// disabled, so that is an error rather than a wrong answer.
+    async fn rx_level(&mut self) -> Result<usize, Error<Spi::Error>> {
+        if !self.fcr.enable() {
+            return Err(Error::FifoDisabled);
+        }
+
+        Ok(self.regs.read_rxlvl(self.channel).await? as usize)
+    }
+
+    /// Returns whether bytes are waiting to be read, in the driver's buffer
+    /// or in the RX FIFO, for code that polls readiness instead of blocking.
+    ///
+    /// This is the async counterpart of [`embedded_io_async::ReadReady`],
+    /// which can't be implemented as it has to answer without touching the
+    /// bus. Under [`RxErrorPolicy::Drop`] a read may still wait if every
+    /// waiting byte turns out to be bad.
+    pub async fn read_ready(&mut self) -> Result<bool, Error<Spi::Error>> {
+        if !self.stash.is_empty() {
+            return Ok(true);
+        }
+        if !self.fcr.enable() {
+            return Ok(self.read_lsr().await?.data_in_receiver());
+        }
+
+        Ok(self.rx_level().await? > 0)
+    }
+
+    /// Returns whether the TX FIFO has space, i.e. whether a
+    /// [`Write::write`] would return without waiting. The async
+    /// counterpart of [`embedded_io_async::WriteReady`].
+    pub async fn write_ready(&mut self) -> Result<bool, Error<Spi::Error>> {
+        Ok(self.tx_space().await? > 0)
+    }
+
+    /// Reads whatever has already arrived, up to `buf.len()` bytes, without
+    /// waiting; returns 0 if nothing has. With the FIFO enabled this is a
+    /// single burst transaction (after RXLVL and LSR) of up to 64 bytes,
+    /// unless a byte in the FIFO has an error.
+    pub async fn try_read(&mut self, buf: &mut [u8]) -> Result<usize, Error<Spi::Error>> {
+        let len = self.take_stash(buf);
+        if len > 0 {
+            return Ok(len);
+        }
+
+        let len = self.read_fifo(buf).await?;
+        if len > 0 {
+            self.wake_if_asleep().await?;
+        }
+        Ok(len)
+    }
+
+    /// Writes as much of `buf` as fits in the TX FIFO right now, in a single
+    /// burst transaction after reading TXLVL; returns 0 if it's full.
+    pub async fn try_write(&mut self, buf: &[u8]) -> Result<usize, Error<Spi::Error>> {
+        let len = buf.len().min(self.tx_space().await?);
+        if len > 0 {
+            self.wake_if_asleep().await?;
+            self.regs.write_many_thr(self.channel, &buf[..len]).await?;
+        }
+        Ok(len)
+    }
+
+    /// Free space in the TX FIFO. With the FIFO disabled TXLVL is
+    /// meaningless, so LSR's THR empty bit stands in for a one-byte FIFO.
+    async fn tx_space(&mut self) -> Result<usize, Error<Spi::Error>> {
+        if !self.fcr.enable() {
+            let lsr = self.read_lsr().await?;
+            return Ok(usize::from(lsr.thr_empty()));
+        }
+
+        Ok(self.regs.read_txlvl(self.channel).await? as usize)
+    }
+
+    /// Moves stashed bytes into `buf`, returning how many were moved.
+    fn take_stash(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.stash.len());
+        for (dst, src) in buf.iter_mut().zip(self.stash.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+
+    /// Reads whatever fits from the RX FIFO without waiting, applying the
+    /// [`RxErrorPolicy`] to bytes received with a parity or framing error.
+    async fn read_fifo(&mut self, buf: &mut [u8]) -> Result<usize, Error<Spi::Error>> {
+        if let Some(error) = self.deferred_rx_error.take() {
+            return Err(error.into());
+        }
+        if !self.fcr.enable() {
+            return self.read_unbuffered(buf).await;
+        }
+
+        let available = buf.len().min(self.rx_level().await?);
+        if available == 0 {
+            return Ok(0);
+        }
+
+        let mut lsr = self.read_lsr().await?;
+        if lsr.overrun_error() && self.overrun_is_error {
+            // Reading LSR cleared the flag; the data stays in the FIFO
+            return Err(Error::Overrun);
+        }
+        if !lsr.fifo_data_error() {
+            self.regs
+                .read_many_rhr(self.channel, &mut buf[..available])
+                .await?;
+            return Ok(available);
+        }
+
+        // Some byte in the FIFO is bad. LSR reflects the byte at the top of
+        // the FIFO, so go one byte at a time.
+        let mut len = 0;
+        for i in 0..available {
+            if i > 0 {
+                lsr = self.read_lsr().await?;
+            }
+            match self.read_rhr_checked(lsr).await {
+                Ok(Some(byte)) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Ok(None) => {}
+                Err(error) => return self.defer_rx_error(error, len),
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// [`Self::read_fifo`] for 16450 mode, where RXLVL is meaningless: LSR
+    /// data ready is polled before every byte.
+    async fn read_unbuffered(&mut self, buf: &mut [u8]) -> Result<usize, Error<Spi::Error>> {
+        let mut len = 0;
+        while len < buf.len() {
+            let lsr = self.read_lsr().await?;
+            if lsr.overrun_error() && self.overrun_is_error {
+                // The bytes already read came before the overrun
+                return self.defer_rx_error(Error::Overrun, len);
+            }
+            if !lsr.data_in_receiver() {
+                break;
+            }
+            match self.read_rhr_checked(lsr).await {
+                Ok(Some(byte)) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Ok(None) => {}
+                Err(error) => return self.defer_rx_error(error, len),
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Returns the `len` good bytes a read has already taken, keeping a
+    /// receive `error` for the next read to report, so that they are not
+    /// lost with it. With nothing taken yet, or for a bus error, the error
+    /// is returned right away.
+    fn defer_rx_error(
+        &mut self,
+        error: Error<Spi::Error>,
+        len: usize,
+    ) -> Result<usize, Error<Spi::Error>> {
+        if len == 0 {
+            return Err(error);
+        }
+
+        self.deferred_rx_error = Some(match error {
+            Error::Overrun => DeferredRxError::Overrun,
+            Error::Break => DeferredRxError::Break,
+            Error::Framing => DeferredRxError::Framing,
+            Error::Parity => DeferredRxError::Parity,
+            error => return Err(error),
+        });
+        Ok(len)
+    }
+
+    /// Reads one byte from RHR, applying the [`RxErrorPolicy`] according to
+    /// `lsr`, which must have been read just before. `None` means the byte
+    /// was dropped.
+    async fn read_rhr_checked(&mut self, lsr: Lsr) -> Result<Option<u8>, Error<Spi::Error>> {
+        let [byte] = self.regs.read(low_level::RHR, self.channel).await?;
+
+        if !lsr.framing_error() && !lsr.parity_error() && !lsr.break_interrupt() {
+            return Ok(Some(byte));
+        }
+
+        match self.rx_error_policy {
+            RxErrorPolicy::Drop => Ok(None),
+            RxErrorPolicy::Deliver => {
+                self.rx_error = true;
+                Ok(Some(self.error_replacement.unwrap_or(byte)))
+            }
+            // A break also shows up as a framing error on a zero byte
+            RxErrorPolicy::Abort if lsr.break_interrupt() => Err(Error::Break),
+            RxErrorPolicy::Abort if lsr.framing_error() => Err(Error::Framing),
+            RxErrorPolicy::Abort => Err(Error::Parity),
+        }
+    }
+
+    /// Sets how reads treat bytes received with a parity or framing error.
+    /// The default is [`RxErrorPolicy::Deliver`].
+    pub fn set_rx_error_policy(&mut self, policy: RxErrorPolicy) {
+        self.rx_error_policy = policy;
+    }
+
+    /// Under [`RxErrorPolicy::Deliver`], replaces bytes received with a
+    /// parity or framing error by `replacement` instead of passing on the
+    /// corrupted value. `None` (the default) passes them on unchanged.
+    pub fn set_error_replacement(&mut self, replacement: Option<u8>) {
+        self.error_replacement = replacement;
+    }
+
+    /// Returns every line error seen in LSR since the last call, whichever
+    /// operation happened to read (and thereby clear) it.
+    pub fn take_accumulated_errors(&mut self) -> LineStatusErrors {
+        LineStatusErrors::from_lsr(Lsr::from_bytes([core::mem::take(&mut self.lsr_errors)]))
+    }
+
+    /// Reads LSR, keeping its sticky error bits for
+    /// [`Self::take_accumulated_errors`]. All LSR reads go through here,
+    /// except [`Self::read_multidrop`]'s.
+    async fn read_lsr(&mut self) -> Result<Lsr, Error<Spi::Error>> {
+        let lsr = self.regs.read_lsr(self.channel).await?;
+        self.accumulate_line_errors(lsr);
+        Ok(lsr)
+    }
+
+    fn accumulate_line_errors(&mut self, lsr: Lsr) {
+        let [bits] = lsr.into_bytes();
+        self.lsr_errors |= bits & LineStatusErrors::LSR_MASK;
+    }
+
+    /// Makes reads fail with [`Error::Overrun`] when the RX FIFO overflowed,
+    /// before any of the data received around the overrun is returned, so
+    /// that a protocol layer can resync. Off by default.
+    ///
+    /// The error is reported once; the next read returns the FIFO contents.
+    /// A read that had already taken bytes received before the overrun
+    /// returns those, and the next one fails.
+    pub fn set_overrun_is_error(&mut self, enabled: bool) {
+        self.overrun_is_error = enabled;
+    }
+
+    /// Returns whether a byte with a parity or framing error has been
+    /// delivered since the last call, under [`RxErrorPolicy::Deliver`].
+    pub fn take_rx_error(&mut self) -> bool {
+        core::mem::take(&mut self.rx_error)
+    }
+
+    /// Bring-up diagnostic: receives `samples` bytes and returns `true` if
+    /// more than half of them carry a framing error, which almost always
+    /// means the baud rate differs from the peer's.
+    ///
+    /// The sampled bytes are discarded.
+    pub async fn estimate_baud_mismatch(&mut self, samples: u8) -> Result<bool, Error<Spi::Error>> {
+        let mut framing_errors = 0;
+
+        for _ in 0..samples {
+            if self.sample_byte().await?.framing_error() {
+                framing_errors += 1;
+            }
+        }
+
+        Ok(framing_errors > samples / 2)
+    }
+
+    /// Experimental: tries each candidate baud rate and framing in turn and
+    /// returns the first under which a few received bytes all arrive without
+    /// framing or parity errors. The candidate stays configured.
+    ///
+    /// If none matches, the original divisor and framing are restored and
+    /// `None` is returned. The sampled bytes are discarded, and sampling
+    /// waits for the peer to send. Fails with [`Error::UnsupportedBaudRate`],
+    /// before changing any setting, if a candidate has no usable divisor.
+    pub async fn probe_peer_framing(
+        &mut self,
+        crystal_freq: u32,
+        candidates: &[(u32, LineConfig)],
+    ) -> Result<Option<(u32, LineConfig)>, Error<Spi::Error>> {
+        const SAMPLES: usize = 8;
+
+        let divisor = self.regs.read_divisor(self.channel).await?;
+        let (baud_rate, line_config) = self.current_config();
+        let prescaler = match self.regs.read_mcr(self.channel).await?.divisor() {
+            Divisor::DivideByOne => Prescaler::DivideByOne,
+            Divisor::DivideByFour => Prescaler::DivideByFour,
+        };
+
+        // Checked up front, so that a bad candidate can't leave the channel
+        // on an earlier one
+        for &(baud, _) in candidates {
+            checked_divisor(baud, crystal_freq, prescaler)?;
+        }
+
+        for &(baud, config) in candidates {
+            let candidate = checked_divisor(baud, crystal_freq, prescaler)?;
+            self.write_divisor_and_line_config(candidate, config)
+                .await?;
+            // Whatever arrived under the previous settings is garbage
+            if self.fcr.enable() {
+                self.write_fcr(self.fcr.with_reset_rx(true)).await?;
+            }
+
+            let mut clean = true;
+            for _ in 0..SAMPLES {
+                let lsr = self.sample_byte().await?;
+                if lsr.framing_error() || lsr.parity_error() {
+                    clean = false;
+                    break;
+                }
+            }
+
+            if clean {
+                self.baud_rate = baud;
+                return Ok(Some((baud, config)));
+            }
+        }
+
+        self.write_divisor_and_line_config(divisor, line_config)
+            .await?;
+        self.baud_rate = baud_rate;
+
+        Ok(None)
+    }
+
+    /// Waits for a received byte and discards it, returning the LSR that
+    /// went with it.
+    async fn sample_byte(&mut self) -> Result<Lsr, Error<Spi::Error>> {
+        let mut armed = false;
+        loop {
+            // LSR reflects the byte at the top of the FIFO
+            let lsr = self.read_lsr().await?;
+            if lsr.data_in_receiver() {
+                self.regs.read(low_level::RHR, self.channel).await?;
+                return Ok(lsr);
+            }
+
+            if !armed {
+                self.enable_rx_interrupt().await?;
+                armed = true;
+            }
+            self.wait_for_irq().await?;
+            self.poll_interrupt().await?;
+        }
+    }
+
+    /// Disables all interrupts of the channel and clears whatever is still
+    /// pending, so that re-enabling them starts from a clean slate.
+    ///
+    /// Pending sources are cleared the way the datasheet prescribes: LSR,
+    /// MSR or IOState is read, and received data is moved out of the FIFO
+    /// into the driver's buffer (so later reads still return it). Gives up
+    /// after a few rounds if a source keeps reasserting, and fails with
+    /// [`Error::BufferFull`] if received data no longer fits in the buffer.
+    pub async fn clear_interrupts(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.regs.write_ier(self.channel, Ier::new()).await?;
+
+        for _ in 0..8 {
+            let Some(source) = self.poll_interrupt().await? else {
+                break;
+            };
+
+            match source {
+                InterruptSource::ReceiveLineStatusError => {
+                    self.read_lsr().await?;
+                }
+                InterruptSource::ReceiverTimeout | InterruptSource::RhrInterrupt => {
+                    let moved = self.stash_rx().await?;
+                    if moved == 0 && self.stash.is_full() {
+                        return Err(Error::BufferFull);
+                    }
+                }
+                InterruptSource::ModemInterrupt => {
+                    self.regs.read_msr(self.channel).await?;
+                }
+                InterruptSource::InputPinChange => {
+                    self.regs.read_iostate().await?;
+                }
+                // Reading IIR is enough to clear the rest
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Times `iterations` scratchpad reads and returns the average time per
+    /// register access, in the unit of `now` (microseconds, typically).
+    ///
+    /// `now` is any monotonic clock, e.g. a closure over the platform's timer.
+    /// The scratchpad is only read, so this does not disturb the UART.
+    pub async fn benchmark_register_access(
+        &mut self,
+        iterations: u32,
+        mut now: impl FnMut() -> u64,
+    ) -> Result<u32, Error<Spi::Error>> {
+        if iterations == 0 {
+            return Ok(0);
+        }
+
+        let start = now();
+        for _ in 0..iterations {
+            self.regs.read(low_level::SPR, self.channel).await?;
+        }
+        let elapsed = now().saturating_sub(start);
+
+        Ok((elapsed / u64::from(iterations)) as u32)
+    }
+
+    /// Reads every readable register of the channel, for debugging or for
+    /// comparing against a known-good snapshot.
+    ///
+    /// This is not free of side effects: reading LSR and MSR clears their
+    /// sticky error and delta bits, and reading IIR clears a pending THR
+    /// interrupt. LCR is briefly modified to read the divisor latch.
+    pub async fn dump_registers(&mut self) -> Result<RegisterDump, Error<Spi::Error>> {
+        let [ier] = self.regs.read(low_level::IER, self.channel).await?;
+        let [iir] = self.regs.read(low_level::IIR, self.channel).await?;
+        let [lcr] = self.regs.read(low_level::LCR, self.channel).await?;
+        let [mcr] = self.regs.read(low_level::MCR, self.channel).await?;
+        let [lsr] = self.read_lsr().await?.into_bytes();
+        let [msr] = self.regs.read(low_level::MSR, self.channel).await?;
+        let [spr] = self.regs.read(low_level::SPR, self.channel).await?;
+        let txlvl = self.regs.read_txlvl(self.channel).await?;
+        let rxlvl = self.regs.read_rxlvl(self.channel).await?;
+        let divisor = self.regs.read_divisor(self.channel).await?;
+
+        Ok(RegisterDump {
+            ier,
+            iir,
+            lcr,
+            mcr,
+            lsr,
+            msr,
+            spr,
+            txlvl,
+            rxlvl,
+            divisor,
+        })
+    }
+
+    /// Reads the raw DLL register (divisor low byte), for debugging.
+    pub async fn read_dll(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.regs.read_latch(low_level::DLL, self.channel).await
+    }
+
+    /// Reads the raw DLH register (divisor high byte), for debugging.
+    pub async fn read_dlh(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.regs.read_latch(low_level::DLH, self.channel).await
+    }
+
+    /// Sends `request`, waits until it is on the wire, then reads the
+    /// response into `response`, returning the number of bytes read.
+    ///
+    /// The RHR interrupt is enabled before anything is transmitted, so a peer
+    /// that replies immediately cannot slip in before the driver listens.
+    /// The other interrupt enables are left as they were.
+    pub async fn transact(
+        &mut self,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, Error<Spi::Error>> {
+        self.enable_rx_interrupt().await?;
+
+        self.write_all(request).await?;
+        self.flush().await?;
+
+        self.read(response).await
+    }
+
+    /// Holds TX low (`LCR[6]`) for `duration`, e.g. to wake a device or to
+    /// start a LIN frame. Flush first, as a character still being sent is
+    /// cut off by the break. Only the break bit of LCR is touched.
+    pub async fn send_break(
+        &mut self,
+        duration: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.set_break(true).await?;
+        delay_for(delay, duration).await;
+        self.set_break(false).await
+    }
+
+    /// Starts or ends a break (`LCR[6]`), for when its length is timed by
+    /// something other than a [`DelayNs`], see [`Self::send_break`].
+    ///
+    /// Received breaks are reported by reads as [`Error::Break`] under
+    /// [`RxErrorPolicy::Abort`], and by [`Self::take_accumulated_errors`].
+    pub async fn set_break(&mut self, enabled: bool) -> Result<(), Error<Spi::Error>> {
+        let lcr = self.regs.read_lcr(self.channel).await?;
+        self.regs
+            .write_lcr(self.channel, lcr.with_break_control_bit(enabled))
+            .await
+    }
+
+    /// Writes `buf` one byte at a time, waiting until each byte has left the
+    /// shift register and then for `gap` before sending the next, for
+    /// peripherals that need inter-byte spacing. A zero gap is a plain
+    /// [`Write::write_all`].
+    pub async fn write_spaced(
+        &mut self,
+        buf: &[u8],
+        gap: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        if gap.is_zero() {
+            return self.write_all(buf).await;
+        }
+
+        for (i, &byte) in buf.iter().enumerate() {
+            if i > 0 {
+                delay_for(delay, gap).await;
+            }
+            self.write_all(&[byte]).await?;
+            self.flush_with_delay(delay).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes as much of `buf` as fits in the TX FIFO, like [`Write::write`],
+    /// then reads TXLVL back to check that the FIFO actually took the bytes
+    /// (guarding against a dropped SPI frame).
+    ///
+    /// The transmitter keeps draining the FIFO during the burst, several
+    /// bytes' worth at high baud rates, so a FIFO level that grew at all
+    /// counts as taken. If it did not, e.g. for a single byte that went
+    /// straight on to the shift register, LSR decides: a transmitter still
+    /// busy was fed by the write, and only one that is idle with nothing
+    /// queued fails with [`Error::WriteNotAccepted`].
+    pub async fn write_confirmed(&mut self, buf: &[u8]) -> Result<usize, Error<Spi::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let before = loop {
+            let space = self.tx_space().await?;
+            if space > 0 {
+                break space;
+            }
+
+            self.wait_for_thr().await?;
+        };
+
+        let len = buf.len().min(before);
+        self.regs.write_many_thr(self.channel, &buf[..len]).await?;
+
+        // Without a clock there is no telling how many character times the
+        // burst took, so any net growth of the FIFO counts as taken
+        let after = self.tx_space().await?;
+        if after < before {
+            return Ok(len);
+        }
+
+        // The bytes may have drained as fast as they came in, in which case
+        // the last of them is still being shifted out
+        if self.read_lsr().await?.thr_tsr_empty() {
+            return Err(Error::WriteNotAccepted);
+        }
+
+        Ok(len)
+    }
+
+    /// Like [`Write::flush`], but sleeps on `delay` between LSR polls for as
+    /// many character times as are still queued, plus one for the shift
+    /// register, instead of yielding. The IRQ pin and IER are not touched,
+    /// so received data is left in the RX FIFO for the next read.
+    pub async fn flush_with_delay(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        let char_time = char_time(self.baud_rate, self.line_config);
+        loop {
+            let lsr = self.read_lsr().await?;
+            if lsr.thr_empty() && lsr.thr_tsr_empty() {
+                return Ok(());
+            }
+
+            let queued = match (lsr.thr_empty(), self.fcr.enable()) {
+                (true, _) => 0,
+                (false, true) => 64 - usize::from(self.regs.read_txlvl(self.channel).await?),
+                (false, false) => 1,
+            };
+            let characters = u32::try_from(queued + 1).unwrap_or(u32::MAX);
+            delay_for(delay, char_time * characters).await;
+        }
+    }
+
+    /// Waits for the THR interrupt, i.e. for space in the TX FIFO. The other
+    /// interrupt enables are left as they were.
+    async fn wait_for_thr(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.wait_for_thr_before(pin!(core::future::pending()))
+            .await
+    }
+
+    /// [`Self::wait_for_thr`], giving up with [`Error::Timeout`] once
+    /// `deadline` completes. IER is restored either way.
+    async fn wait_for_thr_before(
+        &mut self,
+        deadline: Pin<&mut impl Future<Output = ()>>,
+    ) -> Result<(), Error<Spi::Error>> {
+        let ier = self.regs.read_ier(self.channel).await?;
+        self.regs
+            .write_ier(self.channel, ier.with_transmit_holding_register(true))
+            .await?;
+
+        let waited = self.wait_for_irq_before(deadline).await;
+
+        // Read IIR to clear the interrupt
+        let _iir = self.regs.read_iir(self.channel).await?;
+
+        self.regs
+            .write_ier(self.channel, ier.with_transmit_holding_register(false))
+            .await?;
+        waited
+    }
+
+    /// Like [`Read::read`], but fails with [`Error::Timeout`] if no data has
+    /// arrived within `timeout`. Only waiting on the IRQ pin is cut short,
+    /// so nothing already taken out of the FIFO is lost.
+    pub async fn read_with_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<usize, Error<Spi::Error>> {
+        let deadline = pin!(delay.delay_us(duration_us(timeout)));
+        self.read_before(buf, deadline).await
+    }
+
+    /// Like [`Write::write`], but fails with [`Error::Timeout`] if the TX
+    /// FIFO has had no space for `timeout`, e.g. because flow control
+    /// halted the transmitter.
+    pub async fn write_with_timeout(
+        &mut self,
+        buf: &[u8],
+        timeout: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<usize, Error<Spi::Error>> {
+        let deadline = pin!(delay.delay_us(duration_us(timeout)));
+        self.write_before(buf, deadline).await
+    }
+
+    async fn read_before(
+        &mut self,
+        buf: &mut [u8],
+        mut deadline: Pin<&mut impl Future<Output = ()>>,
+    ) -> Result<usize, Error<Spi::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = self.take_stash(buf);
+        if len > 0 {
+            return Ok(len);
+        }
+
+        let mut armed = false;
+        loop {
+            let len = self.read_fifo(buf).await?;
+            if len > 0 {
+                self.wake_if_asleep().await?;
+                return Ok(len);
+            }
+
+            // No data — wait for RHR interrupt, which something like
+            // deinit may have disabled
+            if !armed {
+                self.enable_rx_interrupt().await?;
+                armed = true;
+            }
+            self.wait_for_irq_before(deadline.as_mut()).await?;
+
+            // Read IIR to clear the interrupt. After a spurious wake-up
+            // nothing is pending and we simply go back to waiting.
+            let source = self.poll_interrupt().await?;
+
+            // A line status interrupt (see StreamProfile::LowLatency) stays
+            // asserted until LSR is read, even with the FIFO empty
+            if source == Some(InterruptSource::ReceiveLineStatusError) {
+                let lsr = self.read_lsr().await?;
+                if lsr.overrun_error() && self.overrun_is_error {
+                    return Err(Error::Overrun);
+                }
+            }
+        }
+    }
+
+    async fn write_before(
+        &mut self,
+        buf: &[u8],
+        mut deadline: Pin<&mut impl Future<Output = ()>>,
+    ) -> Result<usize, Error<Spi::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.wake_if_asleep().await?;
+        loop {
+            let space = self.tx_space().await?;
+
+            if space > 0 {
+                let len = buf.len().min(space);
+                self.regs.write_many_thr(self.channel, &buf[..len]).await?;
+                return Ok(len);
+            }
+
+            // No space — wait for the THR interrupt
+            self.wait_for_thr_before(deadline.as_mut()).await?;
+        }
+    }
+
+    /// Services RX and TX after an interrupt in one go: received data is
+    /// read into `rx_buf` and `tx_buf` is written as far as the TX FIFO has
+    /// space, returning `(bytes_read, bytes_written)`.
+    ///
+    /// IIR only reports the highest priority source, so it is re-read after
+    /// each one is serviced until nothing RX/TX related is left pending.
+    /// Other sources are left for the caller.
+    pub async fn service_all(
+        &mut self,
+        rx_buf: &mut [u8],
+        tx_buf: &[u8],
+    ) -> Result<(usize, usize), Error<Spi::Error>> {
+        let mut read = self.take_stash(rx_buf);
+        let mut written = 0;
+
+        // Bounded in case a source cannot be cleared, e.g. with rx_buf full
+        for _ in 0..4 {
+            match self.poll_interrupt().await? {
+                Some(InterruptSource::RhrInterrupt | InterruptSource::ReceiverTimeout) => {
+                    read += self.read_fifo(&mut rx_buf[read..]).await?;
+                }
+                Some(InterruptSource::ThrInterrupt) => {
+                    let space = self.tx_space().await?;
+                    let len = (tx_buf.len() - written).min(space);
+                    if len > 0 {
+                        self.regs
+                            .write_many_thr(self.channel, &tx_buf[written..written + len])
+                            .await?;
+                        written += len;
+                    }
+                }
+                Some(InterruptSource::ReceiveLineStatusError) => {
+                    self.read_lsr().await?;
+                }
+                _ => break,
+            }
+        }
+
+        Ok((read, written))
+    }
+
+    /// Reads one frame of a packet-oriented protocol (e.g. Modbus RTU): bytes
+    /// are collected until the receiver timeout interrupt reports the line
+    /// idle for about four character times, or until `buf` is full. Returns
+    /// the length of the frame; if it did not fit, the rest stays in the RX
+    /// FIFO. Needs the FIFO enabled, as there is no timeout without it.
+    ///
+    /// The timeout only fires while the FIFO holds data, so one byte is
+    /// always left in it after an RX trigger interrupt, otherwise a frame
+    /// ending exactly at a trigger level would never be seen to end.
+    pub async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error<Spi::Error>> {
+        let mut len = self.take_stash(buf);
+        // IER[0] enables the receiver timeout interrupt as well
+        self.enable_rx_interrupt().await?;
+
+        while len < buf.len() {
+            self.wait_for_irq().await?;
+
+            match self.poll_interrupt().await? {
+                Some(InterruptSource::RhrInterrupt) => {
+                    let keep_one = self.rx_level().await?.saturating_sub(1);
+                    let end = buf.len().min(len + keep_one);
+                    len += self.read_fifo(&mut buf[len..end]).await?;
+                }
+                Some(InterruptSource::ReceiverTimeout) => {
+                    len += self.read_fifo(&mut buf[len..]).await?;
+                    break;
+                }
+                Some(InterruptSource::ReceiveLineStatusError) => {
+                    self.read_lsr().await?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// [`Self::read_until_idle`] with a choice, through `on_overflow`, of what
+    /// happens when the frame does not fit in `buf`: fail with
+    /// [`Error::BufferFull`], or return the full buffer and deliver the rest
+    /// of the frame on the next read. Either way the rest stays in the RX
+    /// FIFO, which bounds how much of it is kept.
+    pub async fn read_frame(
+        &mut self,
+        buf: &mut [u8],
+        on_overflow: OnOverflow,
+    ) -> Result<usize, Error<Spi::Error>> {
+        let len = self.read_until_idle(buf).await?;
+
+        // Bytes left over once `buf` is full came in before the line was
+        // seen to go idle, so they belong to the same frame
+        if on_overflow == OnOverflow::Error
+            && len == buf.len()
+            && (!self.stash.is_empty() || self.rx_level().await? > 0)
+        {
+            return Err(Error::BufferFull);
+        }
+
+        Ok(len)
+    }
+
+    /// Reads IIR and returns the highest priority pending interrupt source of
+    /// the channel, or `None` if nothing is pending (e.g. a spurious wake-up,
+    /// or the other channel asserting the shared IRQ line).
+    pub async fn poll_interrupt(&mut self) -> Result<Option<InterruptSource>, Error<Spi::Error>> {
+        let iir = self.regs.read_iir(self.channel).await?;
+        if iir.no_interrupt() {
+            return Ok(None);
+        }
+
+        Ok(iir.source_or_err().ok())
+    }
+
+    /// Decodes the highest priority pending interrupt into an [`Event`],
+    /// without waiting; `None` if nothing is pending.
+    ///
+    /// Reading IIR clears the TX space, Xoff and CTS/RTS interrupts. For
+    /// line errors, modem changes and GPIO changes the register that clears
+    /// the interrupt is read as well and its contents returned. Received
+    /// data is left in the FIFO, so that interrupt stays pending until the
+    /// data is read.
+    pub async fn poll_event(&mut self) -> Result<Option<Event>, Error<Spi::Error>> {
+        let Some(source) = self.poll_interrupt().await? else {
+            return Ok(None);
+        };
+
+        let event = match source {
+            InterruptSource::ReceiveLineStatusError => {
+                Event::LineError(LineStatusErrors::from_lsr(self.read_lsr().await?))
+            }
+            InterruptSource::ReceiverTimeout | InterruptSource::RhrInterrupt => {
+                Event::RxDataAvailable
+            }
+            InterruptSource::ThrInterrupt => Event::TxFifoSpace,
+            InterruptSource::ModemInterrupt => Event::ModemStatusChange(self.modem_status().await?),
+            InterruptSource::InputPinChange => {
+                Event::GpioChange(self.regs.read_iostate().await? ^ self.gpio_active_low)
+            }
+            InterruptSource::Xoff => Event::XoffReceived,
+            InterruptSource::CtsRts => Event::CtsRtsChange,
+        };
+        Ok(Some(event))
+    }
+
+    /// Waits on the IRQ pin until [`Self::poll_event`] has something.
+    pub async fn next_event(&mut self) -> Result<Event, Error<Spi::Error>> {
+        loop {
+            if let Some(event) = self.poll_event().await? {
+                return Ok(event);
+            }
+            self.wait_for_irq().await?;
+        }
+    }
+
+    /// Reads the current interrupt enables, for [`Self::restore_interrupt_config`]
+    /// after a temporary change.
+    pub async fn save_interrupt_config(&mut self) -> Result<IerSnapshot, Error<Spi::Error>> {
+        let [ier] = self.regs.read_ier(self.channel).await?.into_bytes();
+        Ok(IerSnapshot(ier))
+    }
+
+    /// Writes back interrupt enables saved by [`Self::save_interrupt_config`].
+    pub async fn restore_interrupt_config(
+        &mut self,
+        snapshot: IerSnapshot,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.regs
+            .write_ier(self.channel, Ier::from_bytes([snapshot.0]))
+            .await
+    }
+
+    /// Recovers from an SPI frame that was cut off (e.g. by an MCU reset),
+    /// which can leave the chip treating the next bytes as part of it.
+    ///
+    /// A few throwaway scratchpad reads soak up any such bytes, then test
+    /// patterns are written to SPR and read back to check that accesses line
+    /// up again, failing with [`Error::SpiDesync`] if they do not. SPR is
+    /// restored afterwards.
+    pub async fn resync_spi(&mut self) -> Result<(), Error<Spi::Error>> {
+        for _ in 0..2 {
+            self.regs.read(low_level::SPR, self.channel).await?;
+        }
+
+        if self.scratchpad_round_trip().await? != SCRATCHPAD_PATTERNS {
+            return Err(Error::SpiDesync);
+        }
+        Ok(())
+    }
+
+    /// Checks that the chip is there by writing test patterns to SPR and
+    /// reading them back, failing with [`Error::NotDetected`] if they do not
+    /// match. SPR is restored afterwards.
+    pub async fn self_test(&mut self) -> Result<(), Error<Spi::Error>> {
+        if self.scratchpad_round_trip().await? != SCRATCHPAD_PATTERNS {
+            return Err(Error::NotDetected);
+        }
+        Ok(())
+    }
+
+    /// Sends a test pattern through the chip's internal loopback (see
+    /// [`Self::set_loopback`]) at the configured baud rate and checks it
+    /// all the way: empty FIFOs after a reset, a full TXLVL/RXLVL count, a
+    /// clean LSR and the bytes themselves. Fails with
+    /// [`Error::LoopbackFailed`] on any mismatch, and with
+    /// [`Error::FlushTimeout`] if the bytes don't arrive within twice the
+    /// time they take on the wire.
+    ///
+    /// Needs [`Self::init`] (and the FIFOs) first. Both FIFOs are reset
+    /// before and after, so anything queued is lost, and MCR is restored.
+    /// The pattern is cut down to the data bits in use.
+    pub async fn loopback_test(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        const PATTERN: [u8; 8] = [0x00, 0xFF, 0x55, 0xAA, 0x0F, 0xF0, 0x5A, 0xA5];
+        const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+        if self.baud_rate == 0 {
+            return Err(Error::NotInitialized);
+        }
+        if !self.fcr.enable() {
+            return Err(Error::FifoDisabled);
+        }
+
+        let mask = match self.line_config.data_bits {
+            DataBits::Five => 0x1F,
+            DataBits::Six => 0x3F,
+            DataBits::Seven => 0x7F,
+            DataBits::Eight => 0xFF,
+        };
+        let pattern = PATTERN.map(|byte| byte & mask);
+        // Up to 12 bits a character, with parity and two stop bits
+        let timeout =
+            Duration::from_micros(2 * 12 * 1_000_000 * PATTERN.len() as u64) / self.baud_rate;
+
+        let mcr = self.regs.read_mcr(self.channel).await?;
+        self.regs
+            .write_mcr(self.channel, mcr.with_loopback(true))
+            .await?;
+        let result = self
+            .loopback_round_trip(&pattern, timeout, POLL_INTERVAL, delay)
+            .await;
+
+        self.write_fcr(self.fcr.with_reset_rx(true).with_reset_tx(true))
+            .await?;
+        self.regs.write_mcr(self.channel, mcr).await?;
+        result
+    }
+
+    async fn loopback_round_trip(
+        &mut self,
+        pattern: &[u8; 8],
+        timeout: Duration,
+        poll_interval: Duration,
+        delay: &mut impl DelayNs,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write_fcr(self.fcr.with_reset_rx(true).with_reset_tx(true))
+            .await?;
+        self.stash.clear();
+        self.read_lsr().await?;
+        if self.rx_level().await? != 0 || self.tx_space().await? != 64 {
+            return Err(Error::LoopbackFailed);
+        }
+
+        self.regs.write_many_thr(self.channel, pattern).await?;
+
+        let mut waited = Duration::ZERO;
+        while self.rx_level().await? < pattern.len() {
+            if waited >= timeout {
+                return Err(Error::FlushTimeout);
+            }
+            delay_for(delay, poll_interval).await;
+            waited += poll_interval;
+        }
+
+        let lsr = self.read_lsr().await?;
+        let clean = !(lsr.overrun_error()
+            || lsr.parity_error()
+            || lsr.framing_error()
+            || lsr.break_interrupt()
+            || lsr.fifo_data_error());
+        if self.rx_level().await? != pattern.len() || !clean || !lsr.data_in_receiver() {
+            return Err(Error::LoopbackFailed);
+        }
+
+        let mut echoed = [0; 8];
+        self.regs.read_many_rhr(self.channel, &mut echoed).await?;
+        if echoed != *pattern {
+            return Err(Error::LoopbackFailed);
+        }
+        Ok(())
+    }
+
+    /// Makes [`Self::init`] and [`Self::init_with_config`] run
+    /// [`Self::self_test`] before touching any configuration, so that a
+    /// missing or miswired chip fails init instead of reading back zeros.
+    pub fn set_self_test_on_init(&mut self, enabled: bool) {
+        self.self_test_on_init = enabled;
+    }
+
+    /// Records the SPI mode the bus was set up with, for [`Self::probe`].
+    /// The chip only works in mode 0 (CPOL = 0, CPHA = 0), the default
+    /// assumption.
+    pub fn set_spi_mode_assumptions(&mut self, mode: Mode) {
+        self.spi_mode = mode;
+    }
+
+    /// Resets the whole chip (`IOControl[3]`) to its power-on state, both
+    /// channels and the GPIOs included, and forgets what this driver had
+    /// configured; [`Self::init`] has to run again. A driver for the other
+    /// channel is left with stale settings.
+    ///
+    /// Over I2C the chip does not acknowledge the write that resets it, so
+    /// expect the bus to report an error there.
+    pub async fn reset(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.stash.clear();
+        self.fcr = FifoControl::new();
+        self.baud_rate = 0;
+        self.crystal_freq = 0;
+        self.line_config = LineConfig::default();
+        self.lsr_errors = 0;
+        self.rx_error = false;
+        self.asleep = false;
+        self.deferred_rx_error = None;
+
+        self.regs
+            .write_iocontrol(IoControl::new().with_software_reset(true))
+            .await
+    }
+
+    /// Checks that the chip answers by writing test patterns to SPR and
+    /// reading them back (SPR is restored afterwards).
+    ///
+    /// A bus assumed to be in a mode other than 0, or a readback stuck at
+    /// 0x00 or 0xFF (the typical symptom of a wrong SPI mode or a floating
+    /// MISO), fails with [`Error::SpiModeMismatch`]; any other mismatch with
+    /// [`Error::SpiDesync`].
+    pub async fn probe(&mut self) -> Result<(), Error<Spi::Error>> {
+        if self.spi_mode != MODE_0 {
+            return Err(Error::SpiModeMismatch);
+        }
+
+        match self.scratchpad_round_trip().await? {
+            SCRATCHPAD_PATTERNS => Ok(()),
+            [0x00, 0x00] | [0xFF, 0xFF] => Err(Error::SpiModeMismatch),
+            _ => Err(Error::SpiDesync),
+        }
+    }
+
+    /// Writes [`SCRATCHPAD_PATTERNS`] to SPR one after the other, returning
+    /// what was read back after each, then restores SPR.
+    async fn scratchpad_round_trip(&mut self) -> Result<[u8; 2], Error<Spi::Error>> {
+        let saved = self.regs.read(low_level::SPR, self.channel).await?;
+
+        let mut readback = [0; 2];
+        for (pattern, byte) in SCRATCHPAD_PATTERNS.into_iter().zip(&mut readback) {
+            self.regs
+                .write(low_level::SPR, self.channel, [pattern])
+                .await?;
+            [*byte] = self.regs.read(low_level::SPR, self.channel).await?;
+        }
+
+        self.regs.write(low_level::SPR, self.channel, saved).await?;
+        Ok(readback)
+    }
+
+    /// Bring-up check of the IRQ wiring: enables the THR empty interrupt,
+    /// which fires right away on an idle transmitter, and returns whether the
+    /// IRQ pin went low within 1 ms.
+    ///
+    /// The interrupt is cleared and IER restored afterwards, whatever the
+    /// outcome.
+    pub async fn test_irq(&mut self, delay: &mut impl DelayNs) -> Result<bool, Error<Spi::Error>> {
+        let snapshot = self.save_interrupt_config().await?;
+        self.regs
+            .write_ier(
+                self.channel,
+                Ier::new().with_transmit_holding_register(true),
+            )
+            .await?;
+
+        let asserted = with_timeout(self.irq.wait_for_low(), delay.delay_ms(1)).await;
+
+        // Reading IIR clears the THR interrupt
+        let cleared = self.regs.read_iir(self.channel).await;
+        self.restore_interrupt_config(snapshot).await?;
+        cleared?;
+
+        Ok(asserted.transpose().map_err(Error::irq)?.is_some())
+    }
+
+    /// Sets the FIFO trigger levels in steps of four bytes (TLR), overriding
+    /// the coarse FCR triggers of [`Self::set_fifo_triggers`]. Enables
+    /// enhanced functions, which TLR needs.
+    pub async fn set_trigger_levels(
+        &mut self,
+        levels: TriggerLevels,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.batch().trigger_levels(levels).commit().await
+    }
+
+    /// Sets `EFR[4]` if it is not set already, which unlocks `IER[7:4]`, `MCR[7:5]`
+    /// and the TX FIFO trigger.
+    async fn enable_enhanced_functions(&mut self) -> Result<(), Error<Spi::Error>> {
+        let channel = self.channel;
+        self.regs
+            .with_enhanced(channel, async |regs| {
+                let efr = regs.read_efr(channel).await?;
+                if efr.enhanced_functions() {
+                    return Ok(());
+                }
+                regs.write_efr(channel, efr.with_enhanced_functions(true))
+                    .await
+            })
+            .await
+    }
+
+    /// Sets `IER[0]` if it is not set already, leaving the other enables.
+    async fn enable_rx_interrupt(&mut self) -> Result<(), Error<Spi::Error>> {
+        let ier = self.regs.read_ier(self.channel).await?;
+        if !ier.receive_holding_register() {
+            self.regs
+                .write_ier(self.channel, ier.with_receive_holding_register(true))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_irq(&mut self) -> Result<(), Error<Spi::Error>> {
+        self.irq.wait_for_low().await.map_err(Error::irq)
+    }
+
+    async fn wait_for_irq_before(
+        &mut self,
+        deadline: Pin<&mut impl Future<Output = ()>>,
+    ) -> Result<(), Error<Spi::Error>> {
+        with_timeout(self.irq.wait_for_low(), deadline)
+            .await
+            .ok_or(Error::Timeout)?
+            .map_err(Error::irq)
+    }
+}
+
+/// An interrupt as decoded by [`Sc16is752::poll_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// The RX FIFO reached its trigger level, or holds data that has sat
+    /// there for four character times.
+    RxDataAvailable,
+    /// The TX FIFO has dropped below its trigger level.
+    TxFifoSpace,
+    /// The errors LSR reported.
+    LineError(LineStatusErrors),
+    /// A modem input changed. MSR has already been read to tell, which
+    /// cleared its change bits, so they are only reported here.
+    ModemStatusChange(ModemStatus),
+    /// The Xoff character (with software flow control) or a special
+    /// character was received.
+    XoffReceived,
+    /// CTS or RTS changed from active to inactive under auto flow control.
+    CtsRtsChange,
+    /// A GPIO input with its change interrupt enabled changed; the logical
+    /// input levels as a mask, bit `n` being GPIO`n`.
+    GpioChange(u8),
+}
+
+/// The modem inputs as read from MSR, `true` meaning asserted, along with
+/// which of them changed since the previous read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ModemStatus {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+    pub cts_changed: bool,
+    pub dsr_changed: bool,
+    /// RI went from asserted to deasserted.
+    pub ri_ended: bool,
+    pub cd_changed: bool,
+}
+
+impl ModemStatus {
+    /// Whether any input changed since the previous read.
+    pub fn changed(&self) -> bool {
+        self.cts_changed || self.dsr_changed || self.ri_ended || self.cd_changed
+    }
+}
+
+impl From<Msr> for ModemStatus {
+    fn from(msr: Msr) -> Self {
+        ModemStatus {
+            cts: msr.cts(),
+            dsr: msr.dsr(),
+            ri: msr.ri(),
+            cd: msr.cd(),
+            cts_changed: msr.delta_cts(),
+            dsr_changed: msr.delta_dsr(),
+            ri_ended: msr.trailing_edge_ri(),
+            cd_changed: msr.delta_cd(),
+        }
+    }
+}
+
+/// The IrDA pulse width, as a fraction of the bit time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IrdaPulse {
+    /// 3/16, the SIR standard, for up to 115.2 kbit/s.
+    #[default]
+    ThreeSixteenths,
+    /// 1/4, for up to 1.152 Mbit/s. Only the SC16IS760 and SC16IS762 have
+    /// it, see [`Variant::has_fast_irda`].
+    Quarter,
+}
+
+/// A byte received in multidrop mode, see [`Sc16is752::read_multidrop`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MultidropByte {
+    Address(u8),
+    Data(u8),
+}
+
+/// Members of the SC16IS7xx family, which share the register map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Variant {
+    /// One channel, no GPIOs.
+    Sc16is740,
+    /// One channel, eight GPIOs.
+    Sc16is750,
+    /// The SC16IS750 with fast IrDA.
+    Sc16is760,
+    /// Two channels, eight GPIOs.
+    #[default]
+    Sc16is752,
+    /// The SC16IS752 with fast IrDA.
+    Sc16is762,
+}
+
+impl Variant {
+    pub fn channels(self) -> u8 {
+        match self {
+            Variant::Sc16is740 | Variant::Sc16is750 | Variant::Sc16is760 => 1,
+            Variant::Sc16is752 | Variant::Sc16is762 => 2,
+        }
+    }
+
+    pub fn has_gpio(self) -> bool {
+        self != Variant::Sc16is740
+    }
+
+    /// Whether [`IrdaPulse::Quarter`] is available.
+    pub fn has_fast_irda(self) -> bool {
+        matches!(self, Variant::Sc16is760 | Variant::Sc16is762)
+    }
+}
+
+/// The clock prescaler between the crystal and the baud rate generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Prescaler {
+    DivideByOne,
+    DivideByFour,
+}
+
+impl Prescaler {
+    fn factor(self) -> u32 {
+        match self {
+            Prescaler::DivideByOne => 1,
+            Prescaler::DivideByFour => 4,
+        }
+    }
+}
+
+impl From<Prescaler> for Divisor {
+    fn from(prescaler: Prescaler) -> Self {
+        match prescaler {
+            Prescaler::DivideByOne => Divisor::DivideByOne,
+            Prescaler::DivideByFour => Divisor::DivideByFour,
+        }
+    }
+}
+
+/// The baud rate produced by `divisor` with the given crystal and
+/// prescaler.
+pub fn actual_baud(crystal_freq: u32, prescaler: Prescaler, divisor: u16) -> u32 {
+    crystal_freq / prescaler.factor() / (16 * u32::from(divisor.max(1)))
+}
+
+/// How far `actual` is off `requested`, in thousandths of `requested`,
+/// positive when too fast.
+///
+/// ```
+/// use sc16is752_async::baud_error_permille;
+///
+/// // 100 kbit/s from a 14.7456 MHz crystal comes out at 102.4 kbit/s
+/// assert_eq!(baud_error_permille(100_000, 102_400), 24);
+/// ```
+pub fn baud_error_permille(requested: u32, actual: u32) -> i32 {
+    if requested == 0 {
+        return 0;
+    }
+    let diff = i64::from(actual) - i64::from(requested);
+    (diff * 1000 / i64::from(requested)) as i32
+}
+
+/// The divisor for `baud` with `crystal` and no prescaler, or `None` if the
+/// divisor would not fit in DLL/DLH or miss the baud rate by more than 2%.
+///
+/// Usable in const context, so that an impossible combination fails the
+/// build:
+///
+/// ```
+/// use sc16is752_async::divisor_for;
+///
+/// const DIV: u16 = divisor_for(14_745_600, 115_200).unwrap();
+/// assert_eq!(DIV, 8);
+/// ```
+pub const fn divisor_for(crystal: u32, baud: u32) -> Option<u16> {
+    if baud == 0 {
+        return None;
+    }
+
+    let step = 16 * baud as u64;
+    let divisor = (crystal as u64 + step / 2) / step;
+    if divisor == 0 || divisor > u16::MAX as u64 {
+        return None;
+    }
+
+    let actual = crystal as u64 / (16 * divisor);
+    if actual.abs_diff(baud as u64) * 50 > baud as u64 {
+        return None;
+    }
+
+    Some(divisor as u16)
+}
+
+/// The divisor closest to `baud_rate`, or [`Error::UnsupportedBaudRate`] if
+/// that is 0 or does not fit in DLL/DLH.
+fn checked_divisor<SpiErr>(
+    baud_rate: u32,
+    crystal_freq: u32,
+    prescaler: Prescaler,
+) -> Result<u16, Error<SpiErr>> {
+    if baud_rate == 0 {
+        return Err(Error::UnsupportedBaudRate);
+    }
+
+    let clock = u64::from(crystal_freq / prescaler.factor());
+    let step = 16 * u64::from(baud_rate);
+    match (clock + step / 2) / step {
+        0 => Err(Error::UnsupportedBaudRate),
+        divisor => u16::try_from(divisor).map_err(|_| Error::UnsupportedBaudRate),
+    }
+}
+
+/// Line errors reported by LSR, see [`Sc16is752::take_accumulated_errors`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineStatusErrors {
+    pub overrun: bool,
+    pub parity: bool,
+    pub framing: bool,
+    pub break_interrupt: bool,
+    /// At least one byte in the RX FIFO had an error.
+    pub fifo_data_error: bool,
+}
+
+impl LineStatusErrors {
+    /// `LSR[4:1]` and `LSR[7]`.
+    const LSR_MASK: u8 = 0x9E;
+
+    fn from_lsr(lsr: Lsr) -> Self {
+        LineStatusErrors {
+            overrun: lsr.overrun_error(),
+            parity: lsr.parity_error(),
+            framing: lsr.framing_error(),
+            break_interrupt: lsr.break_interrupt(),
+            fifo_data_error: lsr.fifo_data_error(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Interrupt enables saved by [`Sc16is752::save_interrupt_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IerSnapshot(u8);
+
+/// Complementary bit patterns for scratchpad round trips.
+const SCRATCHPAD_PATTERNS: [u8; 2] = [0x55, 0xAA];
+
+/// Lets other tasks run before continuing a polling loop.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// `duration` in whole microseconds, saturating at [`u32::MAX`] (71 minutes).
+fn duration_us(duration: Duration) -> u32 {
+    duration.as_micros().try_into().unwrap_or(u32::MAX)
+}
+
+/// How long one character takes on the wire with `config`, start and stop
+/// bits included, or 100 µs while the baud rate is not known.
+fn char_time(baud_rate: u32, config: LineConfig) -> Duration {
+    if baud_rate == 0 {
+        return Duration::from_micros(100);
+    }
+
+    let data_bits = match config.data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let parity_bits = u64::from(config.parity != Parity::None);
+    let stop_bits = match config.stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    let bits = 1 + data_bits + parity_bits + stop_bits;
+    Duration::from_nanos(bits * 1_000_000_000 / u64::from(baud_rate))
+}
+
+/// Waits for `duration` on `delay`, to the nanosecond up to [`u32::MAX`] ns
+/// (4.29 s) and to the microsecond beyond that, saturating like
+/// [`duration_us`] at 71 minutes.
+async fn delay_for(delay: &mut impl DelayNs, duration: Duration) {
+    match u32::try_from(duration.as_nanos()) {
+        Ok(ns) => delay.delay_ns(ns).await,
+        Err(_) => delay.delay_us(duration_us(duration)).await,
+    }
+}
+
+/// Runs `fut` until it completes, or until `timeout` does first, in which
+/// case `None` is returned.
+async fn with_timeout<F: Future>(fut: F, timeout: impl Future<Output = ()>) -> Option<F::Output> {
+    let mut fut = pin!(fut);
+    let mut timeout = pin!(timeout);
+
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        timeout.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+/// Character framing, 8N1 by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LineConfig {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        LineConfig {
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl LineConfig {
+    fn line_control(self) -> LineControl {
+        LineControl::new()
+            .with_data_bits(self.data_bits)
+            .with_parity(self.parity)
+            .with_stop_bits(self.stop_bits)
+    }
+}
+
+/// Everything [`Sc16is752::init_from_config`] sets up, 8N1 and no prescaler
+/// unless changed.
+///
+/// ```
+/// use sc16is752_async::{Config, DataBits, Parity, StopBits};
+///
+/// let config = Config::new(9600, 1_843_200)
+///     .data_bits(DataBits::Seven)
+///     .parity(Parity::Even)
+///     .stop_bits(StopBits::Two);
+/// assert_eq!(config.line.parity, Parity::Even);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub baud_rate: u32,
+    pub crystal_freq: u32,
+    pub prescaler: Prescaler,
+    pub line: LineConfig,
+}
+
+impl Config {
+    pub fn new(baud_rate: u32, crystal_freq: u32) -> Self {
+        Config {
+            baud_rate,
+            crystal_freq,
+            prescaler: Prescaler::DivideByOne,
+            line: LineConfig::default(),
+        }
+    }
+
+    pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.line.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.line.parity = parity;
+        self
+    }
+
+    /// [`StopBits::Two`] means 1.5 stop bits with five data bits.
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.line.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+}
+
+/// Which direction [`Sc16is752::enable_software_flow_control`] applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SwFlowMode {
+    /// Stop transmitting when the peer sends Xoff.
+    Receive,
+    /// Send Xoff/Xon as the RX FIFO fills and drains.
+    Transmit,
+    Both,
+}
+
+/// RX FIFO levels, in bytes, at which flow control halts and resumes the
+/// peer. The chip works in steps of four bytes, so both are rounded down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlowThresholds {
+    pub halt: u8,
+    pub resume: u8,
+}
+
+/// Flow control options for [`Sc16is752::set_flow_control`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// RTS is deasserted as the RX FIFO fills up and transmission pauses
+    /// while CTS is deasserted, both handled by the chip.
+    RtsCts,
+    /// The chip sends `xoff`/`xon` as the RX FIFO fills and drains, and
+    /// stops transmitting between receiving `xoff` and `xon`. Writes then
+    /// wait for FIFO space as usual, and [`Write::flush`] until the peer
+    /// resumes.
+    XonXoff { xon: u8, xoff: u8 },
+}
+
+/// Settings for [`Sc16is752::configure_hardware_flow`]. The thresholds
+/// follow the same rules as [`FlowThresholds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HwFlowConfig {
+    /// Deassert RTS when the RX FIFO reaches `halt_chars`.
+    pub auto_rts: bool,
+    /// Only transmit while CTS is asserted.
+    pub auto_cts: bool,
+    pub halt_chars: u8,
+    pub resume_chars: u8,
+}
+
+/// Halts the peer with 8 bytes of the RX FIFO to spare and resumes it once
+/// 16 are left.
+impl Default for FlowThresholds {
+    fn default() -> Self {
+        FlowThresholds {
+            halt: 56,
+            resume: 16,
+        }
+    }
+}
+
+impl FlowThresholds {
+    fn to_tcr<SpiErr>(self) -> Result<Tcr, Error<SpiErr>> {
+        if self.halt > 60 || self.halt / 4 <= self.resume / 4 {
+            return Err(Error::InvalidThresholds);
+        }
+
+        Ok(Tcr::new()
+            .with_halt(self.halt / 4)
+            .with_resume(self.resume / 4))
+    }
+}
+
+/// FIFO levels, in bytes, at which the RX and TX interrupts fire, for
+/// [`Sc16is752::set_trigger_levels`]. They are rounded down to multiples
+/// of four, up to 60; a level below four falls back to the FCR trigger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TriggerLevels {
+    /// Fire the RX interrupt once this many bytes have arrived.
+    pub rx: u8,
+    /// Fire the TX interrupt once this many bytes of space are free.
+    pub tx: u8,
+}
+
+impl TriggerLevels {
+    fn to_tlr<SpiErr>(self) -> Result<Tlr, Error<SpiErr>> {
+        if self.rx > 60 || self.tx > 60 {
+            return Err(Error::InvalidTriggerLevel);
+        }
+
+        Ok(Tlr::new().with_rx(self.rx / 4).with_tx(self.tx / 4))
+    }
+}
+
+impl SwFlowMode {
+    /// The (RX, TX) halves of `EFR[3:0]` for this mode.
+    fn efr_bits(self) -> (SwFlow, SwFlow) {
+        match self {
+            SwFlowMode::Receive => (SwFlow::Xon1Xoff1, SwFlow::None),
+            SwFlowMode::Transmit => (SwFlow::None, SwFlow::Xon1Xoff1),
+            SwFlowMode::Both => (SwFlow::Xon1Xoff1, SwFlow::Xon1Xoff1),
+        }
+    }
+}
+
+/// Interrupt tuning presets for [`Sc16is752::set_streaming_profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StreamProfile {
+    /// RX and TX triggers at 8 bytes, with line status interrupts: frequent
+    /// interrupts and small refills. FCR = 0x01, IER = 0x05.
+    LowLatency,
+    /// RX and TX triggers at 56 bytes: fewer interrupts and larger bursts.
+    /// FCR = 0xB1, IER = 0x01.
+    HighThroughput,
+}
+
+impl StreamProfile {
+    fn registers(self) -> (FifoControl, Ier) {
+        match self {
+            StreamProfile::LowLatency => (
+                FifoControl::new()
+                    .with_enable(true)
+                    .with_rx_trigger(RxFifoTrigger::_8)
+                    .with_tx_trigger(TxFifoTrigger::_8),
+                Ier::new()
+                    .with_receive_holding_register(true)
+                    .with_receive_line_status(true),
+            ),
+            StreamProfile::HighThroughput => (
+                FifoControl::new()
+                    .with_enable(true)
+                    .with_rx_trigger(RxFifoTrigger::_56)
+                    .with_tx_trigger(TxFifoTrigger::_56),
+                Ier::new().with_receive_holding_register(true),
+            ),
+        }
+    }
+}
+
+/// How reads treat bytes received with a parity or framing error, or as
+/// part of a break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RxErrorPolicy {
+    /// Skip the byte.
+    Drop,
+    /// Pass the byte on and flag it, see [`Sc16is752::take_rx_error`].
+    Deliver,
+    /// Fail the read with [`Error::Break`], [`Error::Framing`] or
+    /// [`Error::Parity`]. Good bytes read before the bad one are returned
+    /// first, and the next read fails instead.
+    Abort,
+}
+
+/// The receive errors a read can hold back for the next one, see
+/// [`Sc16is752::defer_rx_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeferredRxError {
+    Overrun,
+    Break,
+    Framing,
+    Parity,
+}
+
+impl<E> From<DeferredRxError> for Error<E> {
+    fn from(error: DeferredRxError) -> Self {
+        match error {
+            DeferredRxError::Overrun => Error::Overrun,
+            DeferredRxError::Break => Error::Break,
+            DeferredRxError::Framing => Error::Framing,
+            DeferredRxError::Parity => Error::Parity,
+        }
+    }
+}
+
+/// What [`Sc16is752::read_until`] and [`Sc16is752::read_frame`] do when the
+/// buffer fills up before the end of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OnOverflow {
+    /// Fail with [`Error::BufferFull`].
+    Error,
+    /// Return the full buffer; the rest of the frame is delivered on the
+    /// next read.
+    Buffer,
+}
+
+/// Raw register values of one channel, as returned by
+/// [`Sc16is752::dump_registers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegisterDump {
+    pub ier: u8,
+    pub iir: u8,
+    pub lcr: u8,
+    pub mcr: u8,
+    pub lsr: u8,
+    pub msr: u8,
+    pub spr: u8,
+    pub txlvl: u8,
+    pub rxlvl: u8,
+    pub divisor: u16,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<SpiErr> {
+    #[error("spi error: {0:?}")]
+    Spi(SpiErr),
+    #[error("framing error")]
+    Framing,
+    #[error("parity error")]
+    Parity,
+    #[error("RX FIFO overrun")]
+    Overrun,
+    #[error("break condition on the line")]
+    Break,
+    #[error("timed out waiting for the transmitter to drain")]
+    FlushTimeout,
+    #[error("scratchpad round trip failed")]
+    SpiDesync,
+    #[error("no sensible SPI readback, check the SPI mode and wiring")]
+    SpiModeMismatch,
+    #[error("SC16IS752 not detected")]
+    NotDetected,
+    #[error("baud rate not achievable with this crystal and prescaler")]
+    UnsupportedBaudRate,
+    #[error("transmitter still busy")]
+    TransmitterBusy,
+    #[error("no such GPIO")]
+    InvalidPin,
+    #[error("GPIO not configured as output")]
+    NotAnOutput,
+    #[error("GPIO input latch not enabled")]
+    LatchDisabled,
+    #[error("FIFO disabled")]
+    FifoDisabled,
+    #[error("buffer full")]
+    BufferFull,
+    #[error("write not accepted by the TX FIFO")]
+    WriteNotAccepted,
+    #[error("halt threshold must be above the resume threshold")]
+    InvalidThresholds,
+    #[error("trigger level above 60 bytes")]
+    InvalidTriggerLevel,
+    #[error("not available on this chip variant")]
+    Unsupported,
+    #[error("init has not been run")]
+    NotInitialized,
+    #[error("loopback test failed")]
+    LoopbackFailed,
+    #[error("timed out")]
+    Timeout,
+    /// Waiting on the IRQ pin failed. Only the kind of the GPIO driver's
+    /// error is kept, as it would otherwise need a second type parameter.
+    #[error("IRQ pin error: {0:?}")]
+    Irq(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] digital::ErrorKind),
+}
+
+impl<SpiErr> Error<SpiErr> {
+    pub(crate) fn irq(err: impl digital::Error) -> Self {
+        Error::Irq(err.kind())
+    }
+}
+
+impl<SpiErr: core::fmt::Debug> embedded_io_async::Error for Error<SpiErr> {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Error::Framing | Error::Parity | Error::Overrun | Error::Break => {
+                ErrorKind::InvalidData
+            }
+            Error::FlushTimeout | Error::Timeout => ErrorKind::TimedOut,
+            Error::InvalidPin
+            | Error::InvalidThresholds
+            | Error::InvalidTriggerLevel
+            | Error::UnsupportedBaudRate => ErrorKind::InvalidInput,
+            Error::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl<Spi, Irq, const BUF: usize> ErrorType for Sc16is752<Spi, Irq, BUF>
+where
+    Spi: RegisterInterface,
+{
+    type Error = Error<Spi::Error>;
+}
+
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> Write for Sc16is752<Spi, Irq, BUF> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_before(buf, pin!(core::future::pending())).await
+    }
+
+    /// Waits until the TX FIFO and the shift register are empty (`LSR[5]` and
+    /// `LSR[6]`).
+    ///
+    /// The THR interrupt is asserted as soon as the FIFO is down to its
+    /// trigger level, and the shift register has no interrupt at all, so
+    /// there is nothing to sleep on: LSR is polled, yielding in between.
+    /// Prefer [`Sc16is752::flush_with_delay`] where a delay is at hand.
+    /// Received data is left in the RX FIFO for the next read.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        loop {
+            let lsr = self.read_lsr().await?;
+            if lsr.thr_empty() && lsr.thr_tsr_empty() {
+                return Ok(());
+            }
+
+            yield_now().await;
+        }
+    }
+}
+
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> Read for Sc16is752<Spi, Irq, BUF> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_before(buf, pin!(core::future::pending())).await
+    }
+}
+
+/// Received data is exposed straight from the driver's `BUF`-byte buffer,
+/// which `fill_buf` tops up from the RX FIFO (waiting like [`Read::read`])
+/// once it has been consumed. With `BUF` = 0 there is nowhere to put it, so
+/// that fails with [`Error::BufferFull`].
+impl<Spi: RegisterInterface, Irq: Wait, const BUF: usize> BufRead for Sc16is752<Spi, Irq, BUF> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        if BUF == 0 {
+            return Err(Error::BufferFull);
+        }
+
+        if self.stash.is_empty() {
+            let mut chunk = [0u8; 64];
+            let len = self.read(&mut chunk[..BUF.min(64)]).await?;
+            // Fits, as the stash was empty
+            let _ = self.stash.extend_from_slice(&chunk[..len]);
+        }
+        Ok(&self.stash)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.stash.len());
+        self.stash.drain(..amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+    use core::task::{Context, Waker};
+
+    use super::*;
+    use crate::low_level::{
+        DLH, DLL, EFCR, EFR, FCR, IER, IIR, IOCONTROL, IOSTATE, LCR, LSR, MCR, MSR, RHR, RXLVL,
+        SPR, TCR, THR, TXLVL, XOFF1, XOFF2, XON1,
+    };
+    use crate::mock::{
+        FaultyIrq, MockDelay, MockIrq, MockPin, MockSpi, StuckIrq, Window, block_on,
+    };
+
+    /// A driver on channel A with the FIFOs and the RX interrupt enabled,
+    /// as after `init`.
+    fn uart_with_fifo(spi: &MockSpi) -> Sc16is752<MockSpi, MockIrq> {
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.set_fifo_enabled(true)).unwrap();
+        spi.set(Channel::A, Window::General, IER, 0x01);
+        uart
+    }
+
+    #[test]
+    fn test_wait_for_carrier_loss() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, IER, 0x01);
+
+        // Carrier comes up first, then drops
+        spi.queue(Channel::A, Window::General, IIR, &[0x00, 0x00]);
+        spi.queue(Channel::A, Window::General, MSR, &[0x88, 0x08]);
+
+        block_on(uart.wait_for_carrier_loss()).unwrap();
+
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x08, 0x01]);
+        let msr_reads = spi.log().iter().filter(|a| a.read && a.reg == MSR).count();
+        assert_eq!(msr_reads, 2);
+    }
+
+    #[test]
+    fn test_dump_registers() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        for (reg, value) in [
+            (IER, 0x01),
+            (IIR, 0xC1),
+            (LCR, 0x03),
+            (MCR, 0x80),
+            (LSR, 0x60),
+            (MSR, 0x30),
+            (SPR, 0x5A),
+            (TXLVL, 0x40),
+            (RXLVL, 0x02),
+        ] {
+            spi.set(Channel::B, Window::General, reg, value);
+        }
+        spi.set(Channel::B, Window::Latch, DLL, 0x08);
+        spi.set(Channel::B, Window::Latch, DLH, 0x01);
+
+        let dump = block_on(uart.dump_registers()).unwrap();
+
+        assert_eq!(
+            dump,
+            RegisterDump {
+                ier: 0x01,
+                iir: 0xC1,
+                lcr: 0x03,
+                mcr: 0x80,
+                lsr: 0x60,
+                msr: 0x30,
+                spr: 0x5A,
+                txlvl: 0x40,
+                rxlvl: 0x02,
+                divisor: 0x0108,
+            }
+        );
+        assert_eq!(spi.get(Channel::B, Window::General, LCR), 0x03);
+    }
+
+    #[test]
+    fn test_buf_read() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, RXLVL, 5);
+        spi.queue(Channel::A, Window::General, RHR, b"ab\ncd");
+
+        assert_eq!(block_on(uart.fill_buf()).unwrap(), b"ab\ncd");
+        uart.consume(3);
+        // Served from the buffer without touching the bus
+        let transactions = spi.transactions();
+        assert_eq!(block_on(uart.fill_buf()).unwrap(), b"cd");
+        assert_eq!(spi.transactions(), transactions);
+        uart.consume(2);
+
+        spi.set(Channel::A, Window::General, RXLVL, 1);
+        spi.queue(Channel::A, Window::General, RHR, b"e");
+        assert_eq!(block_on(uart.fill_buf()).unwrap(), b"e");
+
+        let mut uart = Sc16is752::<_, _, 0>::with_capacity(spi.clone(), MockIrq, Channel::A);
+        assert!(matches!(block_on(uart.fill_buf()), Err(Error::BufferFull)));
+    }
+
+    #[test]
+    fn test_read_until_buffers_overflow() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        spi.queue(Channel::A, Window::General, RXLVL, &[9]);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef\nxy");
+
+        let mut buf = [0; 4];
+        let len = block_on(uart.read_until(&mut buf, b'\n', OnOverflow::Buffer)).unwrap();
+        assert_eq!(&buf[..len], b"abcd");
+
+        let mut buf = [0; 8];
+        let len = block_on(uart.read_until(&mut buf, b'\n', OnOverflow::Buffer)).unwrap();
+        assert_eq!(&buf[..len], b"ef\n");
+
+        // The bytes past the terminator are still there for a plain read
+        let len = block_on(uart.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"xy");
+
+        let rhr_reads = spi.log().iter().filter(|a| a.read && a.reg == RHR).count();
+        assert_eq!(rhr_reads, 1);
+    }
+
+    #[test]
+    fn test_read_until_overflow_error() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        spi.queue(Channel::A, Window::General, RXLVL, &[6]);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef");
+
+        let mut buf = [0; 4];
+        let result = block_on(uart.read_until(&mut buf, b'\n', OnOverflow::Error));
+        assert!(matches!(result, Err(Error::BufferFull)));
+    }
+
+    #[test]
+    fn test_read_until_arms_rx() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IER, 0x00);
+
+        spi.queue(Channel::A, Window::General, RXLVL, &[0, 3]);
+        spi.queue(Channel::A, Window::General, RHR, b"ok\n");
+        let mut buf = [0; 8];
+        let len = block_on(uart.read_until(&mut buf, b'\n', OnOverflow::Error)).unwrap();
+        assert_eq!(&buf[..len], b"ok\n");
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x01]);
+    }
+
+    #[test]
+    fn test_read_frame_overflow() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // A frame that fits ends with the timeout and an empty FIFO
+        spi.queue(Channel::A, Window::General, IIR, &[0xCC]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[3, 0]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+        let mut buf = [0; 3];
+        assert_eq!(
+            block_on(uart.read_frame(&mut buf, OnOverflow::Error)).unwrap(),
+            3
+        );
+
+        // Too long: an error, the rest staying in the FIFO
+        spi.queue(Channel::A, Window::General, IIR, &[0xCC]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[6, 4]);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef");
+        let result = block_on(uart.read_frame(&mut buf[..2], OnOverflow::Error));
+        assert!(matches!(result, Err(Error::BufferFull)));
+
+        // Or the head now and the tail on the next read
+        spi.queue(Channel::A, Window::General, IIR, &[0xCC]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[4]);
+        let len = block_on(uart.read_frame(&mut buf[..2], OnOverflow::Buffer)).unwrap();
+        assert_eq!(&buf[..len], b"cd");
+        spi.queue(Channel::A, Window::General, IIR, &[0xCC]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[2, 0]);
+        let len = block_on(uart.read_frame(&mut buf, OnOverflow::Error)).unwrap();
+        assert_eq!(&buf[..len], b"ef");
+    }
+
+    #[test]
+    fn test_streaming_profiles() {
+        for (profile, fcr, ier) in [
+            (StreamProfile::LowLatency, 0x01, 0x05),
+            (StreamProfile::HighThroughput, 0xB1, 0x01),
+        ] {
+            let spi = MockSpi::new();
+            let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+
+            block_on(uart.set_streaming_profile(profile)).unwrap();
+
+            assert_eq!(spi.writes(Channel::A, Window::General, FCR), [fcr]);
+            assert_eq!(spi.writes(Channel::A, Window::General, IER), [ier]);
+        }
+    }
+
+    #[test]
+    fn test_benchmark_register_access() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+
+        // Every SPI transaction takes 12 µs
+        let clock = spi.clone();
+        let now = move || clock.transactions() as u64 * 12;
+
+        let average = block_on(uart.benchmark_register_access(10, now)).unwrap();
+        assert_eq!(average, 12);
+        assert_eq!(spi.transactions(), 10);
+    }
+
+    #[test]
+    fn test_software_flow_control_thresholds() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+
+        block_on(uart.enable_software_flow_control(
+            0x11,
+            0x13,
+            SwFlowMode::Both,
+            Some(FlowThresholds {
+                halt: 48,
+                resume: 16,
+            }),
+        ))
+        .unwrap();
+
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, XON1), [0x11]);
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, XOFF1), [0x13]);
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, EFR), [0x1A]);
+        assert_eq!(spi.writes(Channel::A, Window::General, LCR), [0xBF, 0x03]);
+        assert_eq!(spi.writes(Channel::A, Window::Tcr, TCR), [0x4C]);
+        assert_eq!(spi.writes(Channel::A, Window::General, MCR), [0x04, 0x00]);
+
+        let result = block_on(uart.enable_software_flow_control(
+            0x11,
+            0x13,
+            SwFlowMode::Both,
+            Some(FlowThresholds {
+                halt: 16,
+                resume: 16,
+            }),
+        ));
+        assert!(matches!(result, Err(Error::InvalidThresholds)));
+    }
+
+    #[test]
+    fn test_configure_software_flow_control() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        spi.set(Channel::B, Window::General, LCR, 0x03);
+
+        block_on(uart.configure_software_flow_control(0x11, 0x13, SwFlowMode::Transmit)).unwrap();
+        assert_eq!(spi.writes(Channel::B, Window::Enhanced, XON1), [0x11]);
+        assert_eq!(spi.writes(Channel::B, Window::Enhanced, XOFF1), [0x13]);
+        assert_eq!(spi.writes(Channel::B, Window::Enhanced, EFR), [0x18]);
+        assert_eq!(spi.writes(Channel::B, Window::General, LCR), [0xBF, 0x03]);
+        assert_eq!(spi.writes(Channel::B, Window::Tcr, TCR), [0x4E]);
+    }
+
+    #[test]
+    fn test_configure_hardware_flow() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+        let cfg = HwFlowConfig {
+            auto_rts: true,
+            auto_cts: true,
+            halt_chars: 56,
+            resume_chars: 8,
+        };
+
+        block_on(uart.configure_hardware_flow(cfg)).unwrap();
+        assert_eq!(
+            spi.write_sequence()[1..],
+            [
+                (Window::General, LCR, 0xBF),
+                (Window::Enhanced, EFR, 0xD0),
+                (Window::General, LCR, 0x03),
+                (Window::General, MCR, 0x04),
+                (Window::Tcr, TCR, 0x2E),
+                (Window::General, MCR, 0x00),
+            ]
+        );
+
+        let result = block_on(uart.configure_hardware_flow(HwFlowConfig {
+            halt_chars: 8,
+            resume_chars: 56,
+            ..cfg
+        }));
+        assert!(matches!(result, Err(Error::InvalidThresholds)));
+    }
+
+    #[test]
+    fn test_enter_sleep() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        spi.queue(Channel::A, Window::General, LSR, &[0x20]);
+        let result = block_on(uart.enter_sleep());
+        assert!(matches!(result, Err(Error::TransmitterBusy)));
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        block_on(uart.enter_sleep()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, EFR), 0x10);
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x11);
+        assert!(uart.is_asleep());
+
+        // Woken up by incoming data, which is still there to read
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+        spi.queue(Channel::A, Window::General, RHR, b"hi");
+        block_on(uart.wake()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+        assert!(!uart.is_asleep());
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"hi");
+    }
+
+    #[test]
+    fn test_sleep_cleared_by_traffic() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+
+        block_on(uart.enter_sleep()).unwrap();
+        block_on(uart.write_all(b"ping")).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+        assert!(!uart.is_asleep());
+
+        block_on(uart.enter_sleep()).unwrap();
+        spi.set(Channel::A, Window::General, RXLVL, 4);
+        spi.queue(Channel::A, Window::General, RHR, b"pong");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 4);
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+        assert!(!uart.is_asleep());
+    }
+
+    #[test]
+    fn test_init_transactions() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, LCR, 0xBF);
+
+        block_on(uart.init(115_200, 1_843_200)).unwrap();
+        // LCR closing the enhanced window, FCR, MCR read, LCR/DLL/DLH/LCR, IER
+        assert_eq!(spi.transactions(), 8);
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, LCR),
+            [0x03, 0x83, 0x03]
+        );
+        assert_eq!(spi.writes(Channel::A, Window::General, FCR), [0x07]);
+        assert!(spi.writes(Channel::A, Window::Enhanced, EFR).is_empty());
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 1);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLH), 0);
+    }
+
+    #[test]
+    fn test_set_fifo_triggers() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        block_on(uart.set_fifo_triggers(RxFifoTrigger::_60, TxFifoTrigger::_16)).unwrap();
+        block_on(uart.set_fifo_triggers(RxFifoTrigger::_8, TxFifoTrigger::_8)).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, FCR),
+            [0x07, 0xD1, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_abort_tx() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.set_streaming_profile(StreamProfile::HighThroughput)).unwrap();
+
+        block_on(uart.abort_tx()).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, FCR), [0xB1, 0xB5]);
+        assert_eq!(uart.fcr.into_bytes(), [0xB1]);
+    }
+
+    #[test]
+    fn test_checked_divisor() {
+        for (crystal, baud, prescaler, divisor) in [
+            (1_843_200, 9600, Prescaler::DivideByOne, Some(12)),
+            (1_843_200, 115_200, Prescaler::DivideByOne, Some(1)),
+            (14_745_600, 115_200, Prescaler::DivideByOne, Some(8)),
+            (14_745_600, 9600, Prescaler::DivideByFour, Some(24)),
+            // 6.51 rounds up, not down
+            (12_000_000, 115_200, Prescaler::DivideByOne, Some(7)),
+            (1_843_200, 1_000_000, Prescaler::DivideByOne, None),
+            (14_745_600, 10, Prescaler::DivideByOne, None),
+            (1_843_200, 0, Prescaler::DivideByOne, None),
+        ] {
+            let result = checked_divisor::<Infallible>(baud, crystal, prescaler);
+            assert_eq!(result.ok(), divisor, "{crystal} Hz, {baud} baud");
+        }
+    }
+
+    #[test]
+    fn test_init_with_prescaler() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+
+        let actual = block_on(uart.init_with_prescaler(
+            9600,
+            14_745_600,
+            Prescaler::DivideByFour,
+            LineConfig::default(),
+        ))
+        .unwrap();
+        assert_eq!(actual, 9600);
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x80);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 24);
+        assert_eq!(uart.current_config().0, 9600);
+
+        let result = block_on(uart.init(1_000_000, 1_843_200));
+        assert!(matches!(result, Err(Error::UnsupportedBaudRate)));
+        assert_eq!(spi.writes(Channel::A, Window::Latch, DLL), [24]);
+    }
+
+    #[test]
+    fn test_divisor_for_in_const_context() {
+        const DIV_115200: u16 = divisor_for(1_843_200, 115_200).unwrap();
+        const DIV_9600: Option<u16> = divisor_for(14_745_600, 9600);
+        // Too fast for the crystal, too slow for DLL/DLH, and 44% off
+        const TOO_FAST: Option<u16> = divisor_for(1_843_200, 1_000_000);
+        const TOO_SLOW: Option<u16> = divisor_for(14_745_600, 10);
+        const INEXACT: Option<u16> = divisor_for(1_843_200, 80_000);
+
+        assert_eq!(DIV_115200, 1);
+        assert_eq!(DIV_9600, Some(96));
+        assert_eq!(TOO_FAST, None);
+        assert_eq!(TOO_SLOW, None);
+        assert_eq!(INEXACT, None);
+        assert_eq!(divisor_for(1_843_200, 0), None);
+    }
+
+    #[test]
+    fn test_set_baud_optimal() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+
+        // Exact with the full clock
+        assert_eq!(
+            block_on(uart.set_baud_optimal(115_200, 1_843_200)).unwrap(),
+            115_200
+        );
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x00);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 1);
+
+        // The divisor would overflow DLL/DLH without the prescaler
+        assert_eq!(block_on(uart.set_baud_optimal(10, 14_745_600)).unwrap(), 10);
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, EFR), [0x10]);
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x80);
+        assert_eq!(block_on(uart.read_dlh()).unwrap(), 0x5A);
+        assert_eq!(block_on(uart.read_dll()).unwrap(), 0x00);
+        assert_eq!(uart.current_config().0, 10);
+
+        // Rejected before touching the chip rather than dividing by zero
+        let writes = spi.log().len();
+        assert!(matches!(
+            block_on(uart.set_baud_optimal(0, 14_745_600)),
+            Err(Error::UnsupportedBaudRate)
+        ));
+        assert!(matches!(
+            block_on(uart.set_baud_optimal(1, 14_745_600)),
+            Err(Error::UnsupportedBaudRate)
+        ));
+        assert_eq!(spi.log().len(), writes);
+    }
+
+    #[test]
+    fn test_enable_hardware_flow_control() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        // 7E2, as left by an earlier set_line_config
+        spi.set(Channel::A, Window::General, LCR, 0x1E);
+
+        block_on(uart.enable_hardware_flow_control()).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, LCR), [0xBF, 0x1E]);
+        assert_eq!(spi.writes(Channel::A, Window::Enhanced, EFR), [0xD0]);
+        assert!(block_on(uart.hardware_flow_active()).unwrap());
+    }
+
+    #[test]
+    fn test_set_flow_control() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.init(115_200, 1_843_200)).unwrap();
+
+        block_on(uart.set_flow_control(FlowControl::RtsCts)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, EFR), 0xD0);
+        assert_eq!(spi.writes(Channel::A, Window::Tcr, TCR), [0x4E]);
+
+        block_on(uart.set_flow_control(FlowControl::XonXoff {
+            xon: 0x11,
+            xoff: 0x13,
+        }))
+        .unwrap();
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, EFR), 0x1A);
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, XON1), 0x11);
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, XOFF1), 0x13);
+
+        block_on(uart.set_flow_control(FlowControl::None)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, EFR), 0x10);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x03);
+    }
+
+    #[test]
+    fn test_hardware_flow_active() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+
+        for (efr, active) in [(0x10, false), (0x50, true), (0x80, true), (0xC0, true)] {
+            spi.set(Channel::A, Window::Enhanced, EFR, efr);
+            assert_eq!(block_on(uart.hardware_flow_active()).unwrap(), active);
+        }
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x03);
+    }
+
+    #[test]
+    fn test_probe_peer_framing() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        let seven_e1 = LineConfig {
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+        };
+        let candidates = [
+            (9600, LineConfig::default()),
+            (115_200, LineConfig::default()),
+            (115_200, seven_e1),
+        ];
+
+        // A framing error, then a parity error, then clean data
+        spi.queue(Channel::A, Window::General, LSR, &[0x89, 0x01, 0x85]);
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        let found = block_on(uart.probe_peer_framing(1_843_200, &candidates)).unwrap();
+        assert_eq!(found, Some((115_200, seven_e1)));
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 1);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x1A);
+        assert_eq!(uart.current_config(), (115_200, seven_e1));
+
+        // Nothing matches: back to 115200 7E1
+        spi.set(Channel::A, Window::General, LSR, 0x89);
+        let found = block_on(uart.probe_peer_framing(1_843_200, &candidates[..2])).unwrap();
+        assert_eq!(found, None);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 1);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x1A);
+        assert_eq!(uart.current_config(), (115_200, seven_e1));
+
+        // A zero candidate is rejected before any setting changes
+        let candidates = [(9600, LineConfig::default()), (0, seven_e1)];
+        assert!(matches!(
+            block_on(uart.probe_peer_framing(1_843_200, &candidates)),
+            Err(Error::UnsupportedBaudRate)
+        ));
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 1);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x1A);
+    }
+
+    #[test]
+    fn test_clear_interrupts() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // Line status error, then RX data, then nothing
+        spi.queue(Channel::A, Window::General, IIR, &[0xC6, 0xC4]);
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        spi.queue(Channel::A, Window::General, RXLVL, &[2]);
+        spi.queue(Channel::A, Window::General, RHR, b"hi");
+
+        block_on(uart.clear_interrupts()).unwrap();
+
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x00]);
+        let reads = |reg| spi.log().iter().filter(|a| a.read && a.reg == reg).count();
+        assert_eq!(reads(IIR), 3);
+        // Once for the line status interrupt, once while draining RX
+        assert_eq!(reads(LSR), 2);
+
+        // The drained bytes are not lost
+        let mut buf = [0; 4];
+        let len = block_on(uart.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"hi");
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::<_, _, 4>::with_capacity(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.set_fifo_enabled(true)).unwrap();
+
+        // Six bytes pending, but only four fit in the buffer
+        spi.set(Channel::A, Window::General, IIR, 0xC4);
+        spi.queue(Channel::A, Window::General, RXLVL, &[6, 2]);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef");
+        let result = block_on(uart.clear_interrupts());
+        assert!(matches!(result, Err(Error::BufferFull)));
+
+        let mut buf = [0; 8];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+
+        let mut uart = Sc16is752::<_, _, 0>::with_capacity(spi.clone(), MockIrq, Channel::A);
+        let result = block_on(uart.read_until(&mut buf, b'\n', OnOverflow::Error));
+        assert!(matches!(result, Err(Error::BufferFull)));
+    }
+
+    #[test]
+    fn test_flush_leaves_rx_data() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IIR, 0xC4);
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+        spi.queue(Channel::A, Window::General, RHR, b"hi");
+
+        // Data arrives while the last bytes are still being shifted out
+        spi.queue(Channel::A, Window::General, LSR, &[0x21, 0x21, 0x61]);
+        block_on(uart.flush()).unwrap();
+        assert!(!spi.log().iter().any(|a| a.reg != LSR && a.read));
+        assert!(spi.writes(Channel::A, Window::General, IER).is_empty());
+
+        spi.set(Channel::A, Window::General, LSR, 0x61);
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"hi");
+    }
+
+    #[test]
+    fn test_write_clamps_to_fifo_space() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        let data: [u8; 100] = core::array::from_fn(|i| i as u8);
+
+        spi.queue(Channel::A, Window::General, TXLVL, &[10]);
+        assert_eq!(block_on(uart.write(&data)).unwrap(), 10);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), data[..10]);
+
+        // A full FIFO waits for THR empty instead of returning 0
+        spi.queue(Channel::A, Window::General, TXLVL, &[0, 5]);
+        spi.set(Channel::A, Window::General, IIR, 0xC2);
+        assert_eq!(block_on(uart.write(&data[10..])).unwrap(), 5);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), data[..15]);
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x03, 0x01]);
+    }
+
+    #[test]
+    fn test_write_keeps_other_interrupts() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.set_streaming_profile(StreamProfile::LowLatency)).unwrap();
+
+        spi.queue(Channel::A, Window::General, TXLVL, &[0, 4]);
+        spi.set(Channel::A, Window::General, IIR, 0xC2);
+        assert_eq!(block_on(uart.write(b"data")).unwrap(), 4);
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IER),
+            [0x05, 0x07, 0x05]
+        );
+    }
+
+    #[test]
+    fn test_flush_waits_for_shift_register() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IIR, 0xC2);
+
+        // FIFO draining, then the last character, then idle
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x20, 0x20, 0x60]);
+        block_on(uart.flush()).unwrap();
+        assert!(spi.writes(Channel::A, Window::General, IER).is_empty());
+        assert_eq!(
+            spi.log().iter().filter(|a| a.read && a.reg == LSR).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_flush_with_delay() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.init(115_200, 1_843_200)).unwrap();
+        let ier_writes = spi.writes(Channel::A, Window::General, IER);
+
+        // Three bytes behind the shift register, then the last character
+        spi.set(Channel::A, Window::General, TXLVL, 61);
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x20, 0x60]);
+        let mut delay = MockDelay::default();
+        block_on(uart.flush_with_delay(&mut delay)).unwrap();
+
+        // 86.8 µs per character at 115200 8N1
+        assert_eq!(delay.calls, 2);
+        assert_eq!(delay.total_ns, 4 * 86_805 + 86_805);
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), ier_writes);
+    }
+
+    #[test]
+    fn test_write_confirmed() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // One byte went straight on to the shift register
+        spi.queue(Channel::A, Window::General, TXLVL, &[64, 61]);
+        assert_eq!(block_on(uart.write_confirmed(b"abcd")).unwrap(), 4);
+
+        // A fast line drained most of a 64-byte burst while it was clocked in
+        spi.queue(Channel::A, Window::General, TXLVL, &[64, 52]);
+        assert_eq!(block_on(uart.write_confirmed(&[0x55; 64])).unwrap(), 64);
+
+        // One byte to an idle transmitter: TXLVL does not move, but the
+        // shift register is busy with it
+        spi.queue(Channel::A, Window::General, TXLVL, &[64, 64]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x20]);
+        assert_eq!(block_on(uart.write_confirmed(b"a")).unwrap(), 1);
+
+        // TXLVL did not move and the transmitter is idle
+        spi.queue(Channel::A, Window::General, TXLVL, &[64, 64]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x60]);
+        let result = block_on(uart.write_confirmed(b"abcd"));
+        assert!(matches!(result, Err(Error::WriteNotAccepted)));
+    }
+
+    #[test]
+    fn test_rx_error_policy() {
+        for policy in [
+            RxErrorPolicy::Drop,
+            RxErrorPolicy::Deliver,
+            RxErrorPolicy::Abort,
+        ] {
+            let spi = MockSpi::new();
+            let mut uart = uart_with_fifo(&spi);
+            uart.set_rx_error_policy(policy);
+
+            // The middle byte has a framing error
+            spi.queue(Channel::A, Window::General, RXLVL, &[3]);
+            spi.queue(Channel::A, Window::General, LSR, &[0x81, 0x89, 0x81]);
+            spi.queue(Channel::A, Window::General, RHR, b"abc");
+
+            let mut buf = [0; 4];
+            let result = block_on(uart.read(&mut buf));
+
+            match policy {
+                RxErrorPolicy::Drop => assert_eq!(&buf[..result.unwrap()], b"ac"),
+                RxErrorPolicy::Deliver => {
+                    assert_eq!(&buf[..result.unwrap()], b"abc");
+                    assert!(uart.take_rx_error());
+                    assert!(!uart.take_rx_error());
+                }
+                RxErrorPolicy::Abort => {
+                    // The byte before the bad one comes back first
+                    assert_eq!(&buf[..result.unwrap()], b"a");
+                    let result = block_on(uart.read(&mut buf));
+                    assert!(matches!(result, Err(Error::Framing)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_abort_keeps_good_bytes() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        uart.set_rx_error_policy(RxErrorPolicy::Abort);
+
+        // Two good bytes, then one with a framing error
+        spi.queue(Channel::A, Window::General, RXLVL, &[3]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x81, 0x81, 0x89]);
+        spi.queue(Channel::A, Window::General, RHR, b"abx");
+
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        let result = block_on(uart.read(&mut buf));
+        assert!(matches!(result, Err(Error::Framing)));
+
+        // Reported once; reading carries on afterwards
+        spi.queue(Channel::A, Window::General, RXLVL, &[1]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x01]);
+        spi.queue(Channel::A, Window::General, RHR, b"c");
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 1);
+        assert_eq!(buf[0], b'c');
+    }
+
+    #[test]
+    fn test_error_replacement() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        uart.set_error_replacement(Some(0xFF));
+
+        spi.queue(Channel::A, Window::General, RXLVL, &[3]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x81, 0x85, 0x81]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 3);
+        assert_eq!(&buf[..3], b"a\xFFc");
+        assert!(uart.take_rx_error());
+    }
+
+    #[test]
+    fn test_accumulated_errors() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // The read path clears the framing error and overrun from LSR
+        spi.queue(Channel::A, Window::General, RXLVL, &[2]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x83, 0x89]);
+        spi.queue(Channel::A, Window::General, RHR, b"ab");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        block_on(uart.dump_registers()).unwrap();
+
+        let errors = uart.take_accumulated_errors();
+        assert_eq!(
+            errors,
+            LineStatusErrors {
+                overrun: true,
+                framing: true,
+                fifo_data_error: true,
+                ..Default::default()
+            }
+        );
+        assert!(uart.take_accumulated_errors().is_empty());
+    }
+
+    #[test]
+    fn test_line_errors() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        uart.set_rx_error_policy(RxErrorPolicy::Abort);
+
+        // A break: a zero byte with framing error and break set
+        spi.queue(Channel::A, Window::General, RXLVL, &[1]);
+        spi.queue(Channel::A, Window::General, LSR, &[0x99]);
+        spi.queue(Channel::A, Window::General, RHR, &[0x00]);
+        let mut buf = [0; 4];
+        let error = block_on(uart.read(&mut buf)).unwrap_err();
+        assert!(matches!(error, Error::Break));
+        assert_eq!(
+            embedded_io_async::Error::kind(&error),
+            ErrorKind::InvalidData
+        );
+
+        let error: Error<Infallible> = Error::BufferFull;
+        assert_eq!(embedded_io_async::Error::kind(&error), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_read_until_idle() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // RX trigger with 8 bytes, then the timeout with the remaining 4
+        spi.queue(Channel::A, Window::General, IIR, &[0xC4, 0xCC]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[8, 8, 5]);
+        spi.queue(Channel::A, Window::General, RHR, b"modbus frame");
+        let mut buf = [0; 32];
+        assert_eq!(block_on(uart.read_until_idle(&mut buf)).unwrap(), 12);
+        assert_eq!(&buf[..12], b"modbus frame");
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+
+        // Too long for the buffer: the rest stays in the FIFO
+        spi.queue(Channel::A, Window::General, IIR, &[0xC4]);
+        spi.queue(Channel::A, Window::General, RXLVL, &[16, 16]);
+        spi.queue(Channel::A, Window::General, RHR, b"0123456789abcdef");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read_until_idle(&mut buf)).unwrap(), 4);
+        assert_eq!(&buf, b"0123");
+        let rhr_bytes: usize = spi
+            .log()
+            .iter()
+            .filter(|a| a.read && a.reg == RHR)
+            .map(|a| a.data.len())
+            .sum();
+        assert_eq!(rhr_bytes, 16);
+    }
+
+    #[test]
+    fn test_send_break() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        // 7E2
+        spi.set(Channel::B, Window::General, LCR, 0x1E);
+
+        let mut delay = MockDelay::default();
+        block_on(uart.send_break(Duration::from_millis(2), &mut delay)).unwrap();
+        assert_eq!(spi.writes(Channel::B, Window::General, LCR), [0x5E, 0x1E]);
+        assert_eq!(delay.total_ns, 2_000_000);
+
+        block_on(uart.set_break(true)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, LCR), 0x5E);
+    }
+
+    #[test]
+    fn test_loopback() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, MCR, 0x80);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+
+        block_on(uart.set_loopback(true)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x90);
+
+        assert_eq!(block_on(uart.write(b"ping")).unwrap(), 4);
+        let mut buf = [0; 8];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+
+        block_on(uart.set_loopback(false)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x80);
+    }
+
+    #[test]
+    fn test_try_read_write_burst() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        let data: [u8; 64] = core::array::from_fn(|i| i as u8);
+
+        spi.set(Channel::A, Window::General, TXLVL, 40);
+        let transactions = spi.transactions();
+        assert_eq!(block_on(uart.try_write(&data)).unwrap(), 40);
+        assert_eq!(spi.transactions(), transactions + 2);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), data[..40]);
+
+        spi.set(Channel::A, Window::General, RXLVL, 64);
+        spi.queue(Channel::A, Window::General, RHR, &data);
+        let mut buf = [0; 80];
+        let transactions = spi.transactions();
+        assert_eq!(block_on(uart.try_read(&mut buf)).unwrap(), 64);
+        assert_eq!(spi.transactions(), transactions + 3);
+        assert_eq!(buf[..64], data);
+
+        spi.set(Channel::A, Window::General, RXLVL, 0);
+        spi.set(Channel::A, Window::General, TXLVL, 0);
+        assert_eq!(block_on(uart.try_read(&mut buf)).unwrap(), 0);
+        assert_eq!(block_on(uart.try_write(&data)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_loopback_test() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        let mut delay = MockDelay::default();
+        let result = block_on(uart.loopback_test(&mut delay));
+        assert!(matches!(result, Err(Error::NotInitialized)));
+
+        block_on(uart.init(115_200, 1_843_200)).unwrap();
+        spi.set(Channel::A, Window::General, MCR, 0x02);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::A, Window::General, LSR, 0x61);
+        block_on(uart.loopback_test(&mut delay)).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, THR),
+            [0x00, 0xFF, 0x55, 0xAA, 0x0F, 0xF0, 0x5A, 0xA5]
+        );
+        assert_eq!(spi.writes(Channel::A, Window::General, MCR), [0x12, 0x02]);
+
+        // A framing error on the way back
+        spi.set(Channel::A, Window::General, LSR, 0xE9);
+        let result = block_on(uart.loopback_test(&mut delay));
+        assert!(matches!(result, Err(Error::LoopbackFailed)));
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x02);
+    }
+
+    #[test]
+    fn test_init_from_config() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+
+        let config = Config::new(9600, 1_843_200)
+            .data_bits(DataBits::Five)
+            .parity(Parity::Odd)
+            .stop_bits(StopBits::Two);
+        assert_eq!(block_on(uart.init_from_config(config)).unwrap(), 9600);
+
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x0C);
+        assert_eq!(uart.current_config(), (9600, config.line));
+    }
+
+    #[test]
+    fn test_modem_lines() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        spi.set(Channel::A, Window::General, IOCONTROL, 0x01);
+        block_on(uart.enable_modem_pins()).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IOCONTROL), 0x05);
+
+        block_on(uart.set_dtr(true)).unwrap();
+        block_on(uart.set_rts(true)).unwrap();
+        block_on(uart.set_dtr(false)).unwrap();
+        assert_eq!(
+            spi.writes(Channel::B, Window::General, MCR),
+            [0x01, 0x03, 0x02]
+        );
+
+        // DSR and CD asserted, DSR just changed
+        spi.set(Channel::B, Window::General, MSR, 0xA2);
+        let status = block_on(uart.modem_status()).unwrap();
+        assert!(status.dsr && status.cd && !status.cts && !status.ri);
+        assert!(status.dsr_changed && status.changed());
+    }
+
+    #[test]
+    fn test_irq_error() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), FaultyIrq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        let mut buf = [0; 4];
+        let err = block_on(uart.read(&mut buf)).unwrap_err();
+        assert!(matches!(err, Error::Irq(digital::ErrorKind::Other)));
+        assert_eq!(embedded_io_async::Error::kind(&err), ErrorKind::Other);
+
+        // IER is put back even though the pin failed
+        let result = block_on(uart.test_irq(&mut MockDelay::default()));
+        assert!(matches!(result, Err(Error::Irq(_))));
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+    }
+
+    #[test]
+    fn test_register_cache() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        uart.set_register_cache(true);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        let transactions = spi.transactions();
+        block_on(uart.set_dtr(true)).unwrap();
+        block_on(uart.set_rts(true)).unwrap();
+        // MCR was cached by init's read, so only the writes go out
+        assert_eq!(spi.transactions(), transactions + 2);
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x03);
+
+        // Changed behind the driver's back
+        spi.set(Channel::B, Window::General, MCR, 0x10);
+        block_on(uart.set_rts(false)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x01);
+        spi.set(Channel::B, Window::General, MCR, 0x11);
+        uart.invalidate_register_cache();
+        block_on(uart.set_dtr(false)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x10);
+    }
+
+    #[test]
+    fn test_wait_for_cts() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, IER, 0x01);
+        spi.set(Channel::A, Window::General, IIR, 0xC0);
+        spi.queue(Channel::A, Window::General, MSR, &[0x00, 0x00, 0x11]);
+
+        block_on(uart.wait_for_cts()).unwrap();
+        // RX masked while waiting, then restored
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x08, 0x01]);
+    }
+
+    #[test]
+    fn test_wait_for_modem_change_with_rx_pending() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, IER, 0x05);
+        // RX data still reported once, before the mask took effect
+        spi.queue(Channel::A, Window::General, IIR, &[0xC4, 0xC0]);
+        spi.queue(Channel::A, Window::General, MSR, &[0x00, 0x01]);
+
+        let status = block_on(uart.wait_for_modem_change()).unwrap();
+        assert!(status.changed());
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x08, 0x05]);
+        // The pending byte was left for a later read
+        assert!(!spi.log().iter().any(|a| a.read && a.reg == RHR));
+    }
+
+    #[test]
+    fn test_reset() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        block_on(uart.reset()).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, IOCONTROL), [0x08]);
+        assert_eq!(uart.current_config(), (0, LineConfig::default()));
+        // The crystal frequency is forgotten too, so init has to run again
+        assert!(matches!(
+            block_on(uart.set_baud_rate(9600)),
+            Err(Error::NotInitialized)
+        ));
+    }
+
+    #[test]
+    fn test_variant() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::for_variant(spi.clone(), MockIrq, Variant::Sc16is740);
+        assert!(matches!(
+            block_on(uart.set_gpio(0xFF)),
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            block_on(uart.gpio_port().write_pin(0, true)),
+            Err(Error::Unsupported)
+        ));
+        assert_eq!(spi.transactions(), 0);
+
+        let (_, mut b) = Sc16is752::split(spi.clone(), MockIrq, MockIrq);
+        assert!(matches!(
+            b.set_variant(Variant::Sc16is750),
+            Err(Error::Unsupported)
+        ));
+        assert_eq!(b.variant(), Variant::Sc16is752);
+    }
+
+    #[test]
+    fn test_set_baud_rate() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        assert!(matches!(
+            block_on(uart.set_baud_rate(9600)),
+            Err(Error::NotInitialized)
+        ));
+
+        block_on(uart.init(115_200, 14_745_600)).unwrap();
+        spi.set(Channel::A, Window::General, LCR, 0x1B);
+
+        assert_eq!(block_on(uart.set_baud_rate(9600)).unwrap(), 9600);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLL), 96);
+        assert_eq!(spi.get(Channel::A, Window::Latch, DLH), 0);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x1B);
+        assert!(spi.writes(Channel::A, Window::General, MCR).is_empty());
+
+        // Only reachable with the /4 prescaler
+        assert_eq!(block_on(uart.set_baud_rate(10)).unwrap(), 10);
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x80);
+        assert_eq!(uart.current_config().0, 10);
+
+        assert!(matches!(
+            block_on(uart.set_baud_rate(2_000_000)),
+            Err(Error::UnsupportedBaudRate)
+        ));
+    }
+
+    #[test]
+    fn test_irda() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        block_on(uart.enable_irda(IrdaPulse::ThreeSixteenths)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::Enhanced, EFR), 0x10);
+        assert_eq!(spi.get(Channel::B, Window::General, EFCR), 0x00);
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x40);
+
+        assert!(matches!(
+            block_on(uart.enable_irda(IrdaPulse::Quarter)),
+            Err(Error::Unsupported)
+        ));
+        uart.set_variant(Variant::Sc16is762).unwrap();
+        block_on(uart.enable_irda(IrdaPulse::Quarter)).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, EFCR), 0x80);
+
+        block_on(uart.disable_irda()).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, MCR), 0x00);
+    }
+
+    #[test]
+    fn test_multidrop() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        block_on(uart.enable_multidrop(Some(0x42))).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, XOFF2), 0x42);
+        assert_eq!(spi.get(Channel::A, Window::Enhanced, EFR) & 0x20, 0x20);
+        assert_eq!(spi.get(Channel::A, Window::General, EFCR), 0x01);
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x3B);
+
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        block_on(uart.write_address(0x17)).unwrap();
+        block_on(uart.write_all(b"x")).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"\x17x");
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, LCR).last_chunk(),
+            Some(&[0x3B, 0x2B, 0x3B])
+        );
+
+        uart.take_accumulated_errors();
+        spi.queue(Channel::A, Window::General, RHR, b"\x42d");
+        spi.set(Channel::A, Window::General, LSR, 0x85);
+        assert_eq!(
+            block_on(uart.read_multidrop()).unwrap(),
+            MultidropByte::Address(0x42)
+        );
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        assert_eq!(
+            block_on(uart.read_multidrop()).unwrap(),
+            MultidropByte::Data(b'd')
+        );
+        // The address's parity bit was not taken for a line error
+        assert!(uart.take_accumulated_errors().is_empty());
+
+        spi.queue(Channel::A, Window::General, RHR, b"e");
+        spi.set(Channel::A, Window::General, LSR, 0x89);
+        block_on(uart.read_multidrop()).unwrap();
+        assert!(uart.take_accumulated_errors().framing);
+    }
+
+    #[test]
+    fn test_enable_rs485() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+        block_on(uart.enable_rs485(false)).unwrap();
+        // Other EFCR bits are preserved
+        spi.set(Channel::B, Window::General, EFCR, 0x01);
+        block_on(uart.enable_rs485(true)).unwrap();
+        assert_eq!(spi.writes(Channel::B, Window::General, EFCR), [0x10, 0x31]);
+
+        block_on(uart.disable_rs485()).unwrap();
+        assert_eq!(spi.get(Channel::B, Window::General, EFCR), 0x01);
+    }
+
+    #[test]
+    fn test_current_config() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        let config = LineConfig {
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+        };
+
+        block_on(uart.init_with_config(9600, 1_843_200, config)).unwrap();
+
+        assert_eq!(uart.current_config(), (9600, config));
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x1A);
+    }
+
+    #[test]
+    fn test_set_line_config() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        block_on(uart.set_line_config(LineConfig::default())).unwrap();
+        block_on(uart.set_line_config(LineConfig {
+            data_bits: DataBits::Five,
+            parity: Parity::Mark,
+            stop_bits: StopBits::Two,
+        }))
+        .unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, LCR), [0x03, 0x2C]);
+    }
+
+    #[test]
+    fn test_read_after_spurious_interrupt() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // Woken with nothing pending, then woken again by real data
+        spi.queue(Channel::A, Window::General, RXLVL, &[0, 0, 3]);
+        spi.queue(Channel::A, Window::General, IIR, &[0xC1, 0xC4]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+
+        let mut buf = [0; 4];
+        let len = block_on(uart.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"abc");
+
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        assert_eq!(block_on(uart.poll_interrupt()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_on_receiver_timeout() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // Fewer bytes than the RX trigger level, reported by the timeout
+        spi.queue(Channel::A, Window::General, RXLVL, &[0, 2]);
+        spi.queue(Channel::A, Window::General, IIR, &[0xCC]);
+        spi.queue(Channel::A, Window::General, RHR, b"ok");
+
+        let mut buf = [0; 16];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ok");
+        let iir_reads = spi.log().iter().filter(|a| a.read && a.reg == IIR).count();
+        assert_eq!(iir_reads, 1);
+    }
+
+    #[test]
+    fn test_read_byte_count() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IER, 0x04);
+
+        // Nothing yet, so the RX interrupt is armed before waiting
+        spi.queue(Channel::A, Window::General, RXLVL, &[0, 10, 6]);
+        spi.queue(Channel::A, Window::General, RHR, b"0123456789");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 4);
+        assert_eq!(&buf, b"0123");
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x05]);
+
+        let mut buf = [0; 8];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 6);
+        assert_eq!(&buf[..6], b"456789");
+        // One burst per read
+        assert_eq!(
+            spi.log().iter().filter(|a| a.read && a.reg == RHR).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_split_channels() {
+        let spi = MockSpi::new();
+        let (mut a, mut b) = Sc16is752::split(spi.clone(), MockIrq, MockIrq);
+        block_on(a.set_fifo_enabled(true)).unwrap();
+        block_on(b.set_fifo_enabled(true)).unwrap();
+
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::B, Window::General, TXLVL, 64);
+        block_on(a.write_all(b"AT\r")).unwrap();
+        block_on(b.write_all(b"log")).unwrap();
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"AT\r");
+        assert_eq!(spi.writes(Channel::B, Window::General, THR), b"log");
+
+        spi.set(Channel::B, Window::General, RXLVL, 2);
+        spi.queue(Channel::B, Window::General, RHR, b"ok");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(b.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ok");
+    }
+
+    #[test]
+    fn test_split_concurrent_reads() {
+        let spi = MockSpi::new();
+        let irq = MockPin::default();
+        let dispatcher = IrqDispatcher::<_, 2>::new(irq.clone());
+        let (mut a, mut b) =
+            Sc16is752::split(spi.clone(), dispatcher.handle(), dispatcher.handle());
+        block_on(a.set_fifo_enabled(true)).unwrap();
+        block_on(b.set_fifo_enabled(true)).unwrap();
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        spi.set(Channel::B, Window::General, IIR, 0xC1);
+
+        let (mut buf_a, mut buf_b) = ([0; 4], [0; 4]);
+        {
+            let mut read_a = pin!(a.read(&mut buf_a));
+            let mut read_b = pin!(b.read(&mut buf_b));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(read_a.as_mut().poll(&mut cx).is_pending());
+            assert!(read_b.as_mut().poll(&mut cx).is_pending());
+
+            // Channel B receives; A keeps yielding on the asserted line
+            // rather than spinning, so B gets serviced
+            spi.set(Channel::B, Window::General, RXLVL, 2);
+            spi.queue(Channel::B, Window::General, RHR, b"hi");
+            spi.queue(Channel::B, Window::General, IIR, &[0xC4]);
+            irq.set_low(true);
+            assert!(read_a.as_mut().poll(&mut cx).is_pending());
+            assert!(matches!(read_b.as_mut().poll(&mut cx), Poll::Ready(Ok(2))));
+            assert!(read_a.as_mut().poll(&mut cx).is_pending());
+
+            spi.set(Channel::A, Window::General, RXLVL, 3);
+            spi.queue(Channel::A, Window::General, RHR, b"yes");
+            spi.queue(Channel::A, Window::General, IIR, &[0xC4]);
+            let read = (0..4).find_map(|_| match read_a.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => Some(result),
+                Poll::Pending => None,
+            });
+            assert!(matches!(read, Some(Ok(3))));
+        }
+        assert_eq!(&buf_a[..3], b"yes");
+        assert_eq!(&buf_b[..2], b"hi");
+    }
+
+    #[test]
+    fn test_transact_arms_rx_first() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        // Modem and line status interrupts in use, RX not armed
+        spi.set(Channel::A, Window::General, IER, 0x0C);
+
+        // The peer has already replied by the time the request is flushed
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        spi.queue(Channel::A, Window::General, RXLVL, &[3]);
+        spi.queue(Channel::A, Window::General, RHR, b"ack");
+
+        let mut response = [0; 8];
+        let len = block_on(uart.transact(b"ping", &mut response)).unwrap();
+        assert_eq!(&response[..len], b"ack");
+
+        let writes = spi.write_sequence();
+        let armed = writes
+            .iter()
+            .position(|&w| w == (Window::General, IER, 0x0D));
+        let sent = writes
+            .iter()
+            .position(|&w| w == (Window::General, THR, b'p'));
+        assert!(armed.unwrap() < sent.unwrap());
+        assert_eq!(spi.get(Channel::A, Window::General, IER) & 0x0C, 0x0C);
+    }
+
+    #[test]
+    fn test_service_all() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // RX data and TX space pending at the same time
+        spi.queue(Channel::A, Window::General, IIR, &[0xC4, 0xC2]);
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        spi.queue(Channel::A, Window::General, RXLVL, &[3]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+
+        let mut rx = [0; 8];
+        let (read, written) = block_on(uart.service_all(&mut rx, b"xyz")).unwrap();
+
+        assert_eq!(&rx[..read], b"abc");
+        assert_eq!(written, 3);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"xyz");
+    }
+
+    #[test]
+    fn test_levels_with_fifo_disabled() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.set_fifo_enabled(false)).unwrap();
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::A, Window::General, RXLVL, 5);
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+
+        // One byte at a time, paced by LSR instead of TXLVL
+        assert_eq!(block_on(uart.write(b"abc")).unwrap(), 1);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"a");
+        assert!(!spi.log().iter().any(|a| a.reg == TXLVL));
+
+        // RXLVL is not consulted either; LSR data ready is polled per byte
+        spi.queue(Channel::A, Window::General, LSR, &[0x61, 0x69, 0x60, 0x61]);
+        spi.queue(Channel::A, Window::General, RHR, b"xyz");
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"xy");
+        assert!(uart.take_rx_error());
+        assert!(!spi.log().iter().any(|a| a.reg == RXLVL));
+
+        let mut buf = [0; 1];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 1);
+        assert_eq!(&buf, b"z");
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        spi.set(Channel::A, Window::General, MCR, 0x83);
+
+        // Transmitter drains after a couple of polls
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x20]);
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+        let mut delay = MockDelay::default();
+        block_on(uart.shutdown(Duration::from_millis(10), &mut delay)).unwrap();
+        assert_eq!(delay.calls, 2);
+        assert_eq!(spi.writes(Channel::A, Window::General, FCR), [0x01, 0x00]);
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x00]);
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x80);
+
+        // Never drains: still ends up deinitialized
+        spi.set(Channel::A, Window::General, LSR, 0x00);
+        let mut delay = MockDelay::default();
+        let result = block_on(uart.shutdown(Duration::from_millis(1), &mut delay));
+        assert!(matches!(result, Err(Error::FlushTimeout)));
+        assert_eq!(delay.total_ns, 1_000_000);
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_estimate_baud_mismatch() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        // Four of five bytes with a framing error, one gap with no data
+        spi.queue(
+            Channel::A,
+            Window::General,
+            LSR,
+            &[0x89, 0x00, 0x89, 0x01, 0x89, 0x89],
+        );
+        assert!(block_on(uart.estimate_baud_mismatch(5)).unwrap());
+        assert_eq!(
+            spi.log().iter().filter(|a| a.reg == RHR && a.read).count(),
+            5
+        );
+
+        spi.queue(Channel::A, Window::General, LSR, &[0x01, 0x89, 0x01, 0x01]);
+        assert!(!block_on(uart.estimate_baud_mismatch(4)).unwrap());
+    }
+
+    #[test]
+    fn test_estimate_baud_mismatch_arms_rx() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.clear_interrupts()).unwrap();
+        spi.set(Channel::A, Window::General, IER, 0x00);
+
+        // Nothing has arrived yet, so the RX interrupt has to be enabled
+        spi.queue(Channel::A, Window::General, LSR, &[0x00, 0x01]);
+        assert!(!block_on(uart.estimate_baud_mismatch(1)).unwrap());
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IER).last(),
+            Some(&0x01)
+        );
+    }
+
+    #[test]
+    fn test_write_spaced() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        spi.set(Channel::A, Window::General, LSR, 0x60);
+
+        let mut delay = MockDelay::default();
+        block_on(uart.write_spaced(b"abc", Duration::from_millis(2), &mut delay)).unwrap();
+        assert_eq!(delay.calls, 2);
+        assert_eq!(delay.total_ns, 4_000_000);
+
+        // Every byte goes out in its own frame, followed by a flush
+        let accesses: Vec<_, 16> = spi
+            .log()
+            .iter()
+            .filter(|a| (a.reg == THR && !a.read) || (a.reg == LSR && a.read))
+            .map(|a| (a.reg, a.data[0]))
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                (THR, b'a'),
+                (LSR, 0x60),
+                (THR, b'b'),
+                (LSR, 0x60),
+                (THR, b'c'),
+                (LSR, 0x60)
+            ]
+        );
+
+        // No gap, no spacing
+        let mut delay = MockDelay::default();
+        block_on(uart.write_spaced(b"de", Duration::ZERO, &mut delay)).unwrap();
+        assert_eq!(delay.calls, 0);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"abcde");
+
+        // Past u32::MAX ns the gap is still waited in full
+        let mut delay = MockDelay::default();
+        block_on(uart.write_spaced(b"fg", Duration::from_secs(5), &mut delay)).unwrap();
+        assert_eq!(delay.total_ns, 5_000_000_000);
+    }
+
+    #[test]
+    fn test_resume_from_sleep() {
+        let spi = MockSpi::new();
+        // A fresh driver, as after an MCU reset, facing a configured chip
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, LCR, 0x1A);
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        spi.set(Channel::A, Window::General, IER, 0x0C);
+        spi.set(Channel::A, Window::General, RXLVL, 3);
+        uart.stash.extend_from_slice(b"old").unwrap();
+
+        block_on(uart.resume_from_sleep()).unwrap();
+
+        let (_, config) = uart.current_config();
+        assert_eq!(config.data_bits, DataBits::Seven);
+        assert_eq!(config.parity, Parity::Even);
+        assert!(uart.fcr.enable());
+        // RX FIFO drained, leaving the trigger levels in FCR alone
+        assert!(spi.writes(Channel::A, Window::General, FCR).is_empty());
+        assert!(
+            spi.log()
+                .iter()
+                .any(|a| a.read && a.reg == RHR && a.data.len() == 3)
+        );
+        assert!(spi.writes(Channel::A, Window::General, LCR).is_empty());
+        // Modem and line status enables kept
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x0D]);
+        assert!(uart.stash.is_empty());
+    }
+
+    #[test]
+    fn test_resume_from_sleep_closes_enhanced_window() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        block_on(uart.set_line_config(LineConfig {
+            data_bits: DataBits::Seven,
+            parity: Parity::Odd,
+            stop_bits: StopBits::One,
+        }))
+        .unwrap();
+        spi.set(Channel::A, Window::General, LCR, 0xBF);
+
+        block_on(uart.resume_from_sleep()).unwrap();
+
+        // Back to the framing set before sleep, not 0xBF with LCR[7] cleared
+        assert_eq!(spi.get(Channel::A, Window::General, LCR), 0x0A);
+        let (_, config) = uart.current_config();
+        assert_eq!(config.parity, Parity::Odd);
+    }
+
+    #[test]
+    fn test_save_restore_interrupt_config() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, IER, 0x0D);
+
+        let snapshot = block_on(uart.save_interrupt_config()).unwrap();
+        block_on(uart.set_streaming_profile(StreamProfile::LowLatency)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x05);
+
+        block_on(uart.restore_interrupt_config(snapshot)).unwrap();
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x0D);
+    }
+
+    #[test]
+    fn test_resync_spi() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, SPR, 0x42);
+
+        spi.set_partial_frame(3);
+        block_on(uart.resync_spi()).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, SPR),
+            [0x55, 0xAA, 0x42]
+        );
+
+        // Round trips work normally afterwards
+        block_on(uart.regs.write(SPR, Channel::A, [0x17])).unwrap();
+        assert_eq!(block_on(uart.regs.read(SPR, Channel::A)).unwrap(), [0x17]);
+    }
+
+    #[test]
+    fn test_self_test() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        spi.set(Channel::A, Window::General, SPR, 0x42);
+        block_on(uart.self_test()).unwrap();
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, SPR),
+            [0x55, 0xAA, 0x42]
+        );
+
+        // Nothing on the bus reads back as zeros
+        uart.set_self_test_on_init(true);
+        spi.queue(Channel::A, Window::General, SPR, &[0x00; 3]);
+        let result = block_on(uart.init(9600, 1_843_200));
+        assert!(matches!(result, Err(Error::NotDetected)));
+        assert!(spi.writes(Channel::A, Window::General, FCR).is_empty());
+    }
+
+    #[test]
+    fn test_probe_spi_mode_hint() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        block_on(uart.probe()).unwrap();
+
+        for stuck in [0x00, 0xFF] {
+            spi.queue(Channel::A, Window::General, SPR, &[stuck; 3]);
+            let result = block_on(uart.probe());
+            assert!(matches!(result, Err(Error::SpiModeMismatch)));
+        }
+
+        spi.queue(Channel::A, Window::General, SPR, &[0x00, 0x55, 0x2A]);
+        assert!(matches!(block_on(uart.probe()), Err(Error::SpiDesync)));
+
+        let transactions = spi.transactions();
+        uart.set_spi_mode_assumptions(embedded_hal_async::spi::MODE_3);
+        assert!(matches!(
+            block_on(uart.probe()),
+            Err(Error::SpiModeMismatch)
+        ));
+        assert_eq!(spi.transactions(), transactions);
+    }
+
+    #[test]
+    fn test_irq_wiring_check() {
+        let spi = MockSpi::new();
+        spi.set(Channel::A, Window::General, IER, 0x05);
+        spi.set(Channel::A, Window::General, IIR, 0xC2);
+
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        assert!(block_on(uart.test_irq(&mut MockDelay::default())).unwrap());
+        assert_eq!(spi.writes(Channel::A, Window::General, IER), [0x02, 0x05]);
+
+        let mut uart = Sc16is752::new(spi.clone(), StuckIrq, Channel::A);
+        let mut delay = MockDelay::default();
+        assert!(!block_on(uart.test_irq(&mut delay)).unwrap());
+        assert_eq!(delay.total_ns, 1_000_000);
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, IER),
+            [0x02, 0x05, 0x02, 0x05]
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        uart.set_gpio_active_low(0b0000_0001);
+        spi.set(Channel::A, Window::General, IIR, 0xC1);
+        assert_eq!(block_on(uart.poll_event()).unwrap(), None);
+
+        spi.queue(
+            Channel::A,
+            Window::General,
+            IIR,
+            &[0xC1, 0xC6, 0xCC, 0xC2, 0xC0, 0xF0, 0xD0, 0xE0],
+        );
+        spi.set(Channel::A, Window::General, LSR, 0x68);
+        spi.set(Channel::A, Window::General, MSR, 0x11);
+        spi.set(Channel::A, Window::General, IOSTATE, 0b0000_0110);
+
+        // Waits past the empty IIR
+        let event = block_on(uart.next_event()).unwrap();
+        let Event::LineError(errors) = event else {
+            panic!("{event:?}");
+        };
+        assert!(errors.framing && !errors.overrun);
+
+        assert_eq!(block_on(uart.next_event()).unwrap(), Event::RxDataAvailable);
+        assert_eq!(block_on(uart.next_event()).unwrap(), Event::TxFifoSpace);
+        let Event::ModemStatusChange(status) = block_on(uart.next_event()).unwrap() else {
+            panic!();
+        };
+        assert!(status.cts && status.cts_changed);
+        assert_eq!(
+            block_on(uart.next_event()).unwrap(),
+            Event::GpioChange(0b0000_0111)
+        );
+        assert_eq!(block_on(uart.next_event()).unwrap(), Event::XoffReceived);
+        assert_eq!(block_on(uart.next_event()).unwrap(), Event::CtsRtsChange);
+    }
+
+    #[test]
+    fn test_read_write_timeout() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), StuckIrq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+        let mut delay = MockDelay::default();
+        let timeout = Duration::from_millis(20);
+
+        let mut buf = [0; 4];
+        let err = block_on(uart.read_with_timeout(&mut buf, timeout, &mut delay)).unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+        assert_eq!(embedded_io_async::Error::kind(&err), ErrorKind::TimedOut);
+        assert_eq!(delay.total_ns, 20_000_000);
+
+        let err = block_on(uart.write_with_timeout(b"hi", timeout, &mut delay)).unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+
+        // Data that is already there is returned without waiting
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+        spi.queue(Channel::A, Window::General, RHR, b"ok");
+        let len = block_on(uart.read_with_timeout(&mut buf, timeout, &mut delay)).unwrap();
+        assert_eq!(&buf[..len], b"ok");
+        assert_eq!(delay.calls, 2);
+    }
+
+    #[test]
+    fn test_overrun_surfaced_before_data() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        uart.set_overrun_is_error(true);
+
+        spi.set(Channel::A, Window::General, RXLVL, 2);
+        spi.queue(Channel::A, Window::General, LSR, &[0x03]);
+        spi.queue(Channel::A, Window::General, RHR, b"ab");
+
+        let mut buf = [0; 4];
+        assert!(matches!(block_on(uart.read(&mut buf)), Err(Error::Overrun)));
+        assert!(!spi.log().iter().any(|a| a.read && a.reg == RHR));
+
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+    }
+
+    #[test]
+    fn test_overrun_unbuffered_keeps_earlier_bytes() {
+        let spi = MockSpi::new();
+        let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+        uart.set_overrun_is_error(true);
+
+        // Two clean bytes, then LSR reports an overrun
+        spi.queue(Channel::A, Window::General, LSR, &[0x01, 0x01, 0x03]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+
+        let mut buf = [0; 4];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        assert!(matches!(block_on(uart.read(&mut buf)), Err(Error::Overrun)));
+
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        assert_eq!(block_on(uart.read(&mut buf[..1])).unwrap(), 1);
+        assert_eq!(buf[0], b'c');
+    }
+
+    #[test]
+    fn test_read_write_ready() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+
+        assert!(!block_on(uart.read_ready()).unwrap());
+        assert!(!block_on(uart.write_ready()).unwrap());
+
+        spi.set(Channel::A, Window::General, RXLVL, 1);
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+        assert!(block_on(uart.read_ready()).unwrap());
+        assert!(block_on(uart.write_ready()).unwrap());
+
+        // Unbuffered, LSR data ready decides
+        block_on(uart.set_fifo_enabled(false)).unwrap();
+        spi.set(Channel::A, Window::General, LSR, 0x01);
+        assert!(block_on(uart.read_ready()).unwrap());
+    }
+
+    #[test]
+    fn test_line_status_interrupt_reads_lsr() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        uart.set_overrun_is_error(true);
+
+        // The RX FIFO was drained, but the overrun is still pending
+        spi.set(Channel::A, Window::General, IIR, 0xC6);
+        spi.queue(Channel::A, Window::General, LSR, &[0x02]);
+
+        let mut buf = [0; 4];
+        assert!(matches!(block_on(uart.read(&mut buf)), Err(Error::Overrun)));
+        assert!(uart.take_accumulated_errors().overrun);
+    }
+
+    #[test]
+    fn test_read_divisor_halves() {
+        let spi = MockSpi::new();
+        let mut uart = uart_with_fifo(&spi);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+        spi.set(Channel::A, Window::General, DLL, 0x11);
+        spi.set(Channel::A, Window::General, DLH, 0x22);
+        spi.set(Channel::A, Window::Latch, DLL, 0x34);
+        spi.set(Channel::A, Window::Latch, DLH, 0x12);
+
+        assert_eq!(block_on(uart.read_dll()).unwrap(), 0x34);
+        assert_eq!(block_on(uart.read_dlh()).unwrap(), 0x12);
+        assert_eq!(
+            spi.writes(Channel::A, Window::General, LCR),
+            [0x83, 0x03, 0x83, 0x03]
+        );
     }
 }
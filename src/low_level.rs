@@ -1,12 +1,48 @@
-use embedded_hal_async::spi::SpiDevice;
 use modular_bitfield::prelude::*;
 
 use crate::Error;
+use crate::interface::RegisterInterface;
 
+#[derive(Clone)]
 pub struct RegisterWrapper<Spi> {
-    spi: Spi,
+    bus: Spi,
+    /// Extra turnaround bytes clocked between the RAB and read data.
+    dummy_bytes: usize,
+    /// Last known values of LCR, MCR, IER and EFR of both channels and of
+    /// IODIR, when caching is enabled.
+    shadow: Option<Shadow>,
 }
 
+/// Indexed by [`Shadow::slot`]: LCR, MCR, IER and EFR of channel A, the same
+/// for channel B, then IODIR.
+#[derive(Clone, Default)]
+struct Shadow([Option<u8>; 9]);
+
+impl Shadow {
+    /// Where `reg` is cached, if it is one of the cached registers in the
+    /// window selected by the cached LCR. With LCR unknown, only LCR itself
+    /// and IODIR can be told apart.
+    fn slot(&self, reg: u8, channel: Channel) -> Option<usize> {
+        let base = channel as usize * 4;
+        match reg {
+            LCR => return Some(base),
+            IODIR if channel == Channel::A => return Some(8),
+            _ => {}
+        }
+
+        let lcr = self.0[base]?;
+        match reg {
+            MCR if lcr & 0x80 == 0 => Some(base + 1),
+            IER if lcr & 0x80 == 0 => Some(base + 2),
+            EFR if lcr == LCR_ENHANCED => Some(base + 3),
+            _ => None,
+        }
+    }
+}
+
+/// Upper bound for [`Sc16is752::set_spi_dummy_bytes`](crate::Sc16is752::set_spi_dummy_bytes).
+pub const MAX_DUMMY_BYTES: u8 = 4;
+
 // Register addresses matching reference implementation
 pub const THR: u8 = 0x00; // RhrThr register
 pub const IER: u8 = 0x01;
@@ -14,16 +50,60 @@ pub const FCR: u8 = 0x02; // FcrIir register
 pub const LCR: u8 = 0x03;
 pub const MCR: u8 = 0x04;
 pub const LSR: u8 = 0x05;
+pub const MSR: u8 = 0x06;
+pub const SPR: u8 = 0x07;
 pub const DLL: u8 = 0x00; // Same as THR when LCR[7]=1
 pub const DLH: u8 = 0x01; // Same as IER when LCR[7]=1
 pub const TXLVL: u8 = 0x08;
 pub const RXLVL: u8 = 0x09;
+// GPIO registers are shared by both channels
+pub const IODIR: u8 = 0x0A;
+pub const IOSTATE: u8 = 0x0B;
+pub const IOINTENA: u8 = 0x0C;
+pub const IOCONTROL: u8 = 0x0E;
+pub const EFCR: u8 = 0x0F;
 pub const IIR: u8 = 0x02;
 pub const RHR: u8 = 0x00;
 
-impl<Spi: SpiDevice> RegisterWrapper<Spi> {
-    pub fn new(spi: Spi) -> Self {
-        RegisterWrapper { spi }
+// Enhanced register set, accessible only when LCR=0xBF
+pub const EFR: u8 = 0x02;
+pub const XON1: u8 = 0x04;
+pub const XOFF1: u8 = 0x06;
+pub const XOFF2: u8 = 0x07;
+
+// Accessible only when MCR[2]=1 and EFR[4]=1
+pub const TCR: u8 = 0x06;
+pub const TLR: u8 = 0x07;
+
+/// The LCR value that exposes the enhanced register set.
+pub const LCR_ENHANCED: u8 = 0xBF;
+
+impl<Spi: RegisterInterface> RegisterWrapper<Spi> {
+    pub fn new(bus: Spi) -> Self {
+        RegisterWrapper {
+            bus,
+            dummy_bytes: 0,
+            shadow: None,
+        }
+    }
+
+    /// Enables or disables the write-through cache of LCR, MCR, IER, EFR
+    /// and IODIR. Either way the cache starts out empty.
+    pub fn set_cache_enabled(&mut self, enabled: bool) {
+        self.shadow = enabled.then(Shadow::default);
+    }
+
+    /// Forgets all cached values, so that they are read from the chip again.
+    pub fn invalidate_cache(&mut self) {
+        if let Some(shadow) = &mut self.shadow {
+            *shadow = Shadow::default();
+        }
+    }
+
+    /// Sets how many extra bytes to clock after the RAB of a read before the
+    /// data, at most [`MAX_DUMMY_BYTES`].
+    pub fn set_dummy_bytes(&mut self, count: u8) {
+        self.dummy_bytes = count.min(MAX_DUMMY_BYTES).into();
     }
 
     pub async fn write(
@@ -32,35 +112,55 @@ impl<Spi: SpiDevice> RegisterWrapper<Spi> {
         channel: Channel,
         value: [u8; 1],
     ) -> Result<(), Error<Spi::Error>> {
-        let [rab] = Rab::new()
-            .with_rw(ReadWrite::Write)
-            .with_register(reg)
-            .with_channel(channel)
-            .into_bytes();
-
-        self.spi.write(&[rab, value[0]]).await.map_err(Error::Spi)
+        // A software reset puts every register back to its default
+        if reg == IOCONTROL && IoControl::from_bytes(value).software_reset() {
+            self.invalidate_cache();
+        }
+
+        let result = self
+            .bus
+            .write_register(reg, channel, &value)
+            .await
+            .map_err(Error::Spi);
+
+        if let Some(shadow) = &mut self.shadow
+            && let Some(slot) = shadow.slot(reg, channel)
+        {
+            // After a failed write the register may hold either value
+            shadow.0[slot] = result.is_ok().then_some(value[0]);
+        }
+        result
     }
 
     pub async fn read(&mut self, reg: u8, channel: Channel) -> Result<[u8; 1], Error<Spi::Error>> {
-        let [rab] = Rab::new()
-            .with_rw(ReadWrite::Read)
-            .with_register(reg)
-            .with_channel(channel)
-            .into_bytes();
-        let mut buf = [rab, 0x00];
-
-        self.spi
-            .transfer_in_place(&mut buf)
+        let slot = self.shadow.as_ref().and_then(|shadow| {
+            let slot = shadow.slot(reg, channel)?;
+            Some((slot, shadow.0[slot]))
+        });
+        if let Some((_, Some(value))) = slot {
+            return Ok([value]);
+        }
+
+        let mut value = [0];
+        self.bus
+            .read_register(reg, channel, self.dummy_bytes, &mut value)
             .await
             .map_err(Error::Spi)?;
 
-        Ok([buf[1]])
+        if let (Some(shadow), Some((slot, _))) = (&mut self.shadow, slot) {
+            shadow.0[slot] = Some(value[0]);
+        }
+        Ok(value)
     }
 
     pub async fn read_iir(&mut self, channel: Channel) -> Result<Iir, Error<Spi::Error>> {
         self.read(IIR, channel).await.map(Iir::from_bytes)
     }
 
+    pub async fn read_ier(&mut self, channel: Channel) -> Result<Ier, Error<Spi::Error>> {
+        self.read(IER, channel).await.map(Ier::from_bytes)
+    }
+
     pub async fn write_ier(&mut self, channel: Channel, ier: Ier) -> Result<(), Error<Spi::Error>> {
         self.write(IER, channel, ier.into_bytes()).await
     }
@@ -73,32 +173,137 @@ impl<Spi: SpiDevice> RegisterWrapper<Spi> {
         self.write(FCR, channel, fcr.into_bytes()).await
     }
 
-    // pub async fn write_lcr(
-    //     &mut self,
-    //     channel: Channel,
-    //     lcr: LineControl,
-    // ) -> Result<(), Error<Spi::Error>> {
-    //     self.write(LCR, channel, lcr.into_bytes()).await
-    // }
-
-    // pub async fn write_mcr(
-    //     &mut self,
-    //     channel: Channel,
-    //     mcr: ModemControl,
-    // ) -> Result<(), Error<Spi::Error>> {
-    //     self.write(MCR, channel, mcr.into_bytes()).await
-    // }
-
-    // pub async fn write_divisor(
-    //     &mut self,
-    //     channel: Channel,
-    //     divisor: u16,
-    // ) -> Result<(), Error<Spi::Error>> {
-    //     let [msb, lsb] = divisor.to_be_bytes();
-
-    //     self.write(DLL, channel, [lsb]).await?;
-    //     self.write(DLH, channel, [msb]).await
-    // }
+    pub async fn read_lcr(&mut self, channel: Channel) -> Result<LineControl, Error<Spi::Error>> {
+        self.read(LCR, channel).await.map(LineControl::from_bytes)
+    }
+
+    pub async fn write_lcr(
+        &mut self,
+        channel: Channel,
+        lcr: LineControl,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write(LCR, channel, lcr.into_bytes()).await
+    }
+
+    pub async fn read_mcr(&mut self, channel: Channel) -> Result<ModemControl, Error<Spi::Error>> {
+        self.read(MCR, channel).await.map(ModemControl::from_bytes)
+    }
+
+    pub async fn write_mcr(
+        &mut self,
+        channel: Channel,
+        mcr: ModemControl,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write(MCR, channel, mcr.into_bytes()).await
+    }
+
+    /// Switches LCR to 0xBF so that the enhanced registers are accessible,
+    /// returning the previous LCR for [`Self::exit_enhanced`].
+    pub async fn enter_enhanced(&mut self, channel: Channel) -> Result<u8, Error<Spi::Error>> {
+        let [lcr] = self.read(LCR, channel).await?;
+        self.write(LCR, channel, [LCR_ENHANCED]).await?;
+        Ok(lcr)
+    }
+
+    pub async fn exit_enhanced(
+        &mut self,
+        channel: Channel,
+        lcr: u8,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write(LCR, channel, [lcr]).await
+    }
+
+    /// Runs `f` with the enhanced register set selected. The previous LCR is
+    /// restored afterwards, also when `f` fails.
+    pub async fn with_enhanced<R>(
+        &mut self,
+        channel: Channel,
+        f: impl AsyncFnOnce(&mut Self) -> Result<R, Error<Spi::Error>>,
+    ) -> Result<R, Error<Spi::Error>> {
+        let lcr = self.enter_enhanced(channel).await?;
+        let result = f(self).await;
+        self.exit_enhanced(channel, lcr).await?;
+        result
+    }
+
+    /// Reads EFR. Must be called between [`Self::enter_enhanced`] and
+    /// [`Self::exit_enhanced`].
+    pub async fn read_efr(&mut self, channel: Channel) -> Result<Efr, Error<Spi::Error>> {
+        self.read(EFR, channel).await.map(Efr::from_bytes)
+    }
+
+    /// Writes EFR. Must be called between [`Self::enter_enhanced`] and
+    /// [`Self::exit_enhanced`].
+    pub async fn write_efr(&mut self, channel: Channel, efr: Efr) -> Result<(), Error<Spi::Error>> {
+        self.write(EFR, channel, efr.into_bytes()).await
+    }
+
+    /// Writes TCR, temporarily setting `MCR[2]` to make it accessible. Requires
+    /// enhanced functions (`EFR[4]`) to be enabled.
+    pub async fn write_tcr(&mut self, channel: Channel, tcr: Tcr) -> Result<(), Error<Spi::Error>> {
+        self.write_tcr_window(channel, TCR, tcr.into_bytes()).await
+    }
+
+    /// Writes TLR, like [`Self::write_tcr`].
+    pub async fn write_tlr(&mut self, channel: Channel, tlr: Tlr) -> Result<(), Error<Spi::Error>> {
+        self.write_tcr_window(channel, TLR, tlr.into_bytes()).await
+    }
+
+    async fn write_tcr_window(
+        &mut self,
+        channel: Channel,
+        reg: u8,
+        data: [u8; 1],
+    ) -> Result<(), Error<Spi::Error>> {
+        let mcr = self.read_mcr(channel).await?;
+        self.write_mcr(channel, mcr.with_tcr_tlr_enable(true))
+            .await?;
+        self.write(reg, channel, data).await?;
+        self.write_mcr(channel, mcr).await
+    }
+
+    /// Writes several registers of one channel back to back. The chip takes
+    /// a single register per chip-select frame (only THR/RHR burst), so this
+    /// is still one transaction per write; what it saves is the reads a
+    /// read-modify-write would need, when the caller knows the values.
+    pub async fn write_regs(
+        &mut self,
+        channel: Channel,
+        writes: &[(u8, u8)],
+    ) -> Result<(), Error<Spi::Error>> {
+        for &(reg, value) in writes {
+            self.write(reg, channel, [value]).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes DLL/DLH by briefly setting the divisor latch enable bit,
+    /// restoring the previous LCR afterwards.
+    pub async fn write_divisor(
+        &mut self,
+        channel: Channel,
+        divisor: u16,
+    ) -> Result<(), Error<Spi::Error>> {
+        let [lcr] = self.read(LCR, channel).await?;
+        self.write_divisor_with_lcr(channel, divisor, lcr).await
+    }
+
+    /// [`Self::write_divisor`] without reading LCR first: the latch is
+    /// opened on top of `lcr`, which is what LCR is left at.
+    pub async fn write_divisor_with_lcr(
+        &mut self,
+        channel: Channel,
+        divisor: u16,
+        lcr: u8,
+    ) -> Result<(), Error<Spi::Error>> {
+        let [msb, lsb] = divisor.to_be_bytes();
+
+        self.write_regs(
+            channel,
+            &[(LCR, lcr | 0x80), (DLL, lsb), (DLH, msb), (LCR, lcr)],
+        )
+        .await
+    }
 
     pub async fn read_txlvl(&mut self, channel: Channel) -> Result<u8, Error<Spi::Error>> {
         self.read(TXLVL, channel).await.map(|[byte]| byte)
@@ -108,59 +313,126 @@ impl<Spi: SpiDevice> RegisterWrapper<Spi> {
         self.read(RXLVL, channel).await.map(|[byte]| byte)
     }
 
+    /// Burst write into the TX FIFO, at most 64 bytes (the FIFO size).
     pub async fn write_many_thr(
         &mut self,
         channel: Channel,
         data: &[u8],
     ) -> Result<(), Error<Spi::Error>> {
-        let [rab] = Rab::new()
-            .with_rw(ReadWrite::Write)
-            .with_register(THR)
-            .with_channel(channel)
-            .into_bytes();
-
-        // Burst write: single SPI frame with RAB + all data bytes.
-        // Max 64 bytes of data (FIFO size) + 1 RAB byte.
-        let mut buf = [0u8; 65];
-        buf[0] = rab;
         let len = data.len().min(64);
-        buf[1..1 + len].copy_from_slice(&data[..len]);
-        self.spi
-            .write(&buf[..1 + len])
+        self.bus
+            .write_register(THR, channel, &data[..len])
             .await
             .map_err(Error::Spi)
     }
 
-
+    /// Burst read from the RX FIFO, at most 64 bytes.
     pub async fn read_many_rhr(
         &mut self,
         channel: Channel,
         data: &mut [u8],
     ) -> Result<(), Error<Spi::Error>> {
-        let [rab] = Rab::new()
-            .with_rw(ReadWrite::Read)
-            .with_register(RHR)
-            .with_channel(channel)
-            .into_bytes();
-
-        // Burst read: single SPI frame with RAB + N dummy bytes, returns N data bytes.
-        let mut buf = [0u8; 65];
-        buf[0] = rab;
         let len = data.len().min(64);
-        self.spi
-            .transfer_in_place(&mut buf[..1 + len])
+        self.bus
+            .read_register(RHR, channel, self.dummy_bytes, &mut data[..len])
             .await
-            .map_err(Error::Spi)?;
-        data[..len].copy_from_slice(&buf[1..1 + len]);
-        Ok(())
+            .map_err(Error::Spi)
+    }
+
+    pub async fn read_iodir(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.read(IODIR, Channel::A).await.map(|[byte]| byte)
+    }
+
+    pub async fn write_iodir(&mut self, dirs: u8) -> Result<(), Error<Spi::Error>> {
+        self.write(IODIR, Channel::A, [dirs]).await
+    }
+
+    pub async fn read_iostate(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.read(IOSTATE, Channel::A).await.map(|[byte]| byte)
+    }
+
+    pub async fn write_iostate(&mut self, levels: u8) -> Result<(), Error<Spi::Error>> {
+        self.write(IOSTATE, Channel::A, [levels]).await
+    }
+
+    pub async fn read_iointena(&mut self) -> Result<u8, Error<Spi::Error>> {
+        self.read(IOINTENA, Channel::A).await.map(|[byte]| byte)
+    }
+
+    pub async fn write_iointena(&mut self, mask: u8) -> Result<(), Error<Spi::Error>> {
+        self.write(IOINTENA, Channel::A, [mask]).await
+    }
+
+    pub async fn read_iocontrol(&mut self) -> Result<IoControl, Error<Spi::Error>> {
+        self.read(IOCONTROL, Channel::A)
+            .await
+            .map(IoControl::from_bytes)
+    }
+
+    pub async fn write_iocontrol(&mut self, ioc: IoControl) -> Result<(), Error<Spi::Error>> {
+        self.write(IOCONTROL, Channel::A, ioc.into_bytes()).await
+    }
+
+    pub async fn read_efcr(&mut self, channel: Channel) -> Result<Efcr, Error<Spi::Error>> {
+        self.read(EFCR, channel).await.map(Efcr::from_bytes)
+    }
+
+    pub async fn write_efcr(
+        &mut self,
+        channel: Channel,
+        efcr: Efcr,
+    ) -> Result<(), Error<Spi::Error>> {
+        self.write(EFCR, channel, efcr.into_bytes()).await
     }
 
     pub async fn read_lsr(&mut self, channel: Channel) -> Result<Lsr, Error<Spi::Error>> {
         self.read(LSR, channel).await.map(Lsr::from_bytes)
     }
+
+    /// Reads DLL/DLH by briefly setting the divisor latch enable bit,
+    /// restoring the previous LCR afterwards.
+    pub async fn read_divisor(&mut self, channel: Channel) -> Result<u16, Error<Spi::Error>> {
+        let [lcr] = self.read(LCR, channel).await?;
+        self.read_divisor_with_lcr(channel, lcr).await
+    }
+
+    /// [`Self::read_divisor`] without reading LCR first, restoring it to
+    /// `lcr` afterwards.
+    pub async fn read_divisor_with_lcr(
+        &mut self,
+        channel: Channel,
+        lcr: u8,
+    ) -> Result<u16, Error<Spi::Error>> {
+        self.write(LCR, channel, [lcr | 0x80]).await?;
+
+        let [lsb] = self.read(DLL, channel).await?;
+        let [msb] = self.read(DLH, channel).await?;
+
+        self.write(LCR, channel, [lcr]).await?;
+
+        Ok(u16::from_be_bytes([msb, lsb]))
+    }
+
+    /// Reads a single divisor latch register (DLL or DLH), with the same
+    /// LCR save/restore as [`Self::read_divisor`].
+    pub async fn read_latch(&mut self, reg: u8, channel: Channel) -> Result<u8, Error<Spi::Error>> {
+        let [lcr] = self.read(LCR, channel).await?;
+        self.write(LCR, channel, [lcr | 0x80]).await?;
+
+        let [byte] = self.read(reg, channel).await?;
+
+        self.write(LCR, channel, [lcr]).await?;
+
+        Ok(byte)
+    }
+
+    pub async fn read_msr(&mut self, channel: Channel) -> Result<Msr, Error<Spi::Error>> {
+        self.read(MSR, channel).await.map(Msr::from_bytes)
+    }
 }
 
-#[derive(Specifier, Debug, Clone, Copy)]
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[bits = 2] // sic!
 pub enum Channel {
     A = 0b00,
@@ -168,25 +440,29 @@ pub enum Channel {
 }
 
 #[derive(Specifier, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[bits = 1]
-enum ReadWrite {
+pub enum ReadWrite {
     Write = 0,
     Read = 1,
 }
 
+/// Register address byte, the first byte of every SPI transaction.
 #[bitfield(bits = 8)]
-struct Rab {
+#[derive(Clone, Copy)]
+pub struct Rab {
     #[skip]
     unused: B1,
     #[skip(getters)]
-    channel: Channel,
+    pub channel: Channel,
     #[skip(getters)]
-    register: B4,
+    pub register: B4,
     #[skip(getters)]
-    rw: ReadWrite,
+    pub rw: ReadWrite,
 }
 
-#[derive(Specifier)]
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[bits = 5]
 pub enum InterruptSource {
     ReceiveLineStatusError = 0b00011,
@@ -194,17 +470,23 @@ pub enum InterruptSource {
     RhrInterrupt = 0b00010,
     ThrInterrupt = 0b00001,
     ModemInterrupt = 0b00000,
+    InputPinChange = 0b11000,
+    Xoff = 0b01000,
+    CtsRts = 0b10000,
 }
 
 #[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
 pub struct Iir {
-    pub pending: bool,
+    /// `IIR[0]` is active low: it is set when *no* interrupt is pending.
+    pub no_interrupt: bool,
     pub source: InterruptSource,
-    #[skip(getters)]
-    fcr_msb: B2,
+    /// `IIR[7:6]` mirror `FCR[0]`, so both are set while the FIFOs are enabled.
+    pub fifos_enabled: B2,
 }
 
 #[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
 pub struct Ier {
     pub receive_holding_register: bool,
     pub transmit_holding_register: bool,
@@ -216,7 +498,8 @@ pub struct Ier {
     pub cts: bool,
 }
 
-#[derive(Specifier)]
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RxFifoTrigger {
     _8 = 0b00,
     _16 = 0b01,
@@ -224,7 +507,8 @@ pub enum RxFifoTrigger {
     _60 = 0b11,
 }
 
-#[derive(Specifier)]
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TxFifoTrigger {
     _8 = 0b00,
     _16 = 0b01,
@@ -233,6 +517,7 @@ pub enum TxFifoTrigger {
 }
 
 #[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
 pub struct FifoControl {
     pub enable: bool,
     pub reset_rx: bool,
@@ -243,30 +528,76 @@ pub struct FifoControl {
     pub rx_trigger: RxFifoTrigger,
 }
 
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 2]
+pub enum DataBits {
+    Five = 0b00,
+    Six = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 1]
+pub enum StopBits {
+    One = 0,
+    /// 1.5 stop bits with five data bits, two otherwise.
+    Two = 1,
+}
+
+/// `LCR[5:3]`: parity enable, even parity and forced parity.
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 3]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    /// Parity bit forced to 1.
+    Mark = 0b101,
+    /// Parity bit forced to 0.
+    Space = 0b111,
+}
+
 #[bitfield(bits = 8)]
 #[derive(Debug, Clone, Copy)]
 pub struct LineControl {
-    #[skip]
-    unused: B6,
+    pub data_bits: DataBits,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
     pub break_control_bit: bool,
     pub divisor_latch_enable: bool,
 }
 
-#[derive(Specifier)]
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Divisor {
     DivideByOne = 0,
     DivideByFour = 1,
 }
 
 #[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy)]
 pub struct ModemControl {
+    pub dtr: bool,
+    pub rts: bool,
+    pub tcr_tlr_enable: bool,
     #[skip]
-    unused: B7,
-    #[skip(getters)]
-    divisor: Divisor,
+    __: B1,
+    /// Internal loopback: TX feeds RX and the modem outputs feed the inputs.
+    pub loopback: bool,
+    #[skip]
+    __: B1,
+    /// IrDA SIR encoding instead of plain UART; needs `EFR[4]`.
+    pub irda: bool,
+    /// Clock prescaler; only writable while `EFR[4]` is set.
+    pub divisor: Divisor,
 }
 
 #[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy)]
 pub struct Lsr {
     pub data_in_receiver: bool,
     pub overrun_error: bool,
@@ -275,13 +606,150 @@ pub struct Lsr {
     pub break_interrupt: bool,
     pub thr_empty: bool,
     pub thr_tsr_empty: bool,
+    /// Set while at least one byte in the RX FIFO has an error.
+    pub fifo_data_error: bool,
+}
+
+/// Which Xon/Xoff characters the chip sends or acts on.
+#[derive(Specifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[bits = 2]
+pub enum SwFlow {
+    None = 0b00,
+    Xon2Xoff2 = 0b01,
+    Xon1Xoff1 = 0b10,
+    Both = 0b11,
+}
+
+#[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy)]
+pub struct Efr {
+    pub rx_sw_flow: SwFlow,
+    pub tx_sw_flow: SwFlow,
+    pub enhanced_functions: bool,
+    pub special_char_detect: bool,
+    pub auto_rts: bool,
+    pub auto_cts: bool,
+}
+
+/// Flow control halt/resume levels, in units of four RX FIFO bytes.
+#[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
+pub struct Tcr {
+    pub halt: B4,
+    pub resume: B4,
+}
+
+/// FIFO trigger levels, in units of four bytes. A zero level leaves that
+/// FIFO on its FCR trigger.
+#[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
+pub struct Tlr {
+    pub tx: B4,
+    pub rx: B4,
+}
+
+/// Modem status. The state bits are the complement of the input pins, so a
+/// set bit means the (active low) signal is asserted.
+#[bitfield(bits = 8)]
+#[derive(Clone, Copy)]
+pub struct Msr {
+    pub delta_cts: bool,
+    pub delta_dsr: bool,
+    pub trailing_edge_ri: bool,
+    pub delta_cd: bool,
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+/// Extra features control register.
+#[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy)]
+pub struct Efcr {
+    /// 9-bit (multidrop) mode.
+    pub multidrop: bool,
+    pub rx_disable: bool,
+    pub tx_disable: bool,
     #[skip]
-    unused: B1,
+    __: B1,
+    /// The transmitter drives RTS for RS-485 direction control.
+    pub rts_control: bool,
+    /// Drive RTS high rather than low while transmitting.
+    pub rts_invert: bool,
+    #[skip]
+    __: B1,
+    /// 1/4 rather than 3/16 bit IrDA pulses (SC16IS760 only).
+    pub irda_fast: bool,
+}
+
+/// IODir: a set bit makes the GPIO an output.
+#[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpioDir {
+    pub gpio0: bool,
+    pub gpio1: bool,
+    pub gpio2: bool,
+    pub gpio3: bool,
+    pub gpio4: bool,
+    pub gpio5: bool,
+    pub gpio6: bool,
+    pub gpio7: bool,
+}
+
+#[bitfield(bits = 8)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoControl {
+    /// Latch IOState on input changes until it is read.
+    pub io_latch: bool,
+    /// `GPIO[7:4]` act as the modem pins of channel A.
+    pub modem_pins_a: bool,
+    /// `GPIO[3:0]` act as the modem pins of channel B.
+    pub modem_pins_b: bool,
+    pub software_reset: bool,
+    #[skip]
+    __: B4,
+}
+
+/// With the `defmt` feature, registers are logged as their raw value rather
+/// than field by field, which keeps the log frames small.
+#[cfg(feature = "defmt")]
+macro_rules! format_as_byte {
+    ($($reg:ident),* $(,)?) => {
+        $(
+            impl defmt::Format for $reg {
+                fn format(&self, f: defmt::Formatter) {
+                    let [byte] = self.into_bytes();
+                    defmt::write!(f, "{=str}({=u8:#04x})", stringify!($reg), byte);
+                }
+            }
+        )*
+    };
 }
 
+#[cfg(feature = "defmt")]
+format_as_byte!(
+    Rab,
+    Iir,
+    Ier,
+    FifoControl,
+    LineControl,
+    ModemControl,
+    Lsr,
+    Efr,
+    Tcr,
+    Tlr,
+    Msr,
+    Efcr,
+    GpioDir,
+    IoControl,
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockSpi, Window, block_on};
 
     #[test]
     fn test_rab_construction() {
@@ -306,4 +774,85 @@ mod tests {
             .with_channel(Channel::B);
         assert_eq!(rab.into_bytes()[0], 0xC2);
     }
+
+    #[test]
+    fn test_write_divisor_sequence() {
+        let spi = MockSpi::new();
+        let mut regs = RegisterWrapper::new(spi.clone());
+        spi.set(Channel::A, Window::General, LCR, 0x1B);
+
+        block_on(regs.write_divisor(Channel::A, 0x0102)).unwrap();
+
+        assert_eq!(
+            spi.write_sequence(),
+            [
+                (Window::General, LCR, 0x9B),
+                (Window::Latch, DLL, 0x02),
+                (Window::Latch, DLH, 0x01),
+                (Window::General, LCR, 0x1B),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_with_dummy_bytes() {
+        let spi = MockSpi::new();
+        let mut regs = RegisterWrapper::new(spi.clone());
+        spi.set_turnaround(1);
+        spi.set(Channel::A, Window::General, SPR, 0x5A);
+        spi.queue(Channel::A, Window::General, RHR, b"ab");
+
+        regs.set_dummy_bytes(1);
+        assert_eq!(block_on(regs.read(SPR, Channel::A)).unwrap(), [0x5A]);
+
+        let mut data = [0; 2];
+        block_on(regs.read_many_rhr(Channel::A, &mut data)).unwrap();
+        assert_eq!(&data, b"ab");
+    }
+
+    #[test]
+    fn test_register_cache() {
+        let spi = MockSpi::new();
+        let mut regs = RegisterWrapper::new(spi.clone());
+        regs.set_cache_enabled(true);
+        spi.set(Channel::A, Window::General, LCR, 0x03);
+        spi.set(Channel::A, Window::General, MCR, 0x02);
+        spi.set(Channel::A, Window::Enhanced, EFR, 0x10);
+        spi.set(Channel::A, Window::Enhanced, XON1, 0x11);
+
+        // MCR can't be told from XON1 until LCR is known
+        block_on(regs.read_mcr(Channel::A)).unwrap();
+        block_on(regs.read_lcr(Channel::A)).unwrap();
+        block_on(regs.read_lcr(Channel::A)).unwrap();
+        block_on(regs.read_mcr(Channel::A)).unwrap();
+        block_on(regs.read_mcr(Channel::A)).unwrap();
+        assert_eq!(spi.transactions(), 3);
+
+        let lcr = block_on(regs.enter_enhanced(Channel::A)).unwrap();
+        assert_eq!(lcr, 0x03);
+        assert_eq!(block_on(regs.read(XON1, Channel::A)).unwrap(), [0x11]);
+        block_on(regs.read_efr(Channel::A)).unwrap();
+        assert_eq!(
+            block_on(regs.read_efr(Channel::A)).unwrap().into_bytes(),
+            [0x10]
+        );
+        block_on(regs.exit_enhanced(Channel::A, lcr)).unwrap();
+        assert_eq!(spi.transactions(), 7);
+
+        // Writes go through and are remembered
+        block_on(regs.write_mcr(Channel::A, ModemControl::from_bytes([0x03]))).unwrap();
+        assert_eq!(
+            block_on(regs.read_mcr(Channel::A)).unwrap().into_bytes(),
+            [0x03]
+        );
+        assert_eq!(spi.get(Channel::A, Window::General, MCR), 0x03);
+        assert_eq!(spi.transactions(), 8);
+
+        // The other channel has its own copy, and a reset drops both
+        block_on(regs.read_lcr(Channel::B)).unwrap();
+        assert_eq!(spi.transactions(), 9);
+        block_on(regs.write_iocontrol(IoControl::new().with_software_reset(true))).unwrap();
+        block_on(regs.read_lcr(Channel::A)).unwrap();
+        assert_eq!(spi.transactions(), 11);
+    }
 }
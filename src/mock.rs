@@ -0,0 +1,526 @@
+//! In-memory stand-ins for the SPI bus and IRQ pin, used by the unit tests
+//! and, with the `mock` feature, available for testing code built on the
+//! driver on the host. The feature links `std`.
+//!
+//! [`MockSpi`] decodes the RAB byte of every transaction and keeps a small
+//! model of the register file, including the LCR-gated register windows, so
+//! that tests can both script read values and inspect what the driver wrote.
+//! With `MCR[4]` set, bytes written to THR are queued on RHR (and counted by
+//! RXLVL) like in the chip's internal loopback.
+
+#![cfg_attr(test, allow(dead_code, reason = "not every test uses every helper"))]
+
+extern crate std;
+
+use core::{
+    convert::Infallible,
+    future::{Future, poll_fn},
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+    vec::Vec,
+};
+
+use embedded_hal_async::{
+    delay::DelayNs,
+    digital::Wait,
+    i2c::{self, I2c},
+    spi::{ErrorType, Operation, SpiDevice},
+};
+
+use crate::low_level::{Channel, LCR, MCR, RHR, RXLVL, THR};
+
+/// The register set an access landed in, as selected by LCR (and MCR/EFR for
+/// TCR/TLR) at the time of the access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Window {
+    /// The registers visible with the divisor latch closed, and everything
+    /// from 0x08 up.
+    General,
+    /// DLL/DLH, visible while `LCR[7]` is set.
+    Latch,
+    /// EFR and Xon/Xoff, visible while LCR is 0xBF.
+    Enhanced,
+    /// TCR/TLR, visible while `MCR[2]` and `EFR[4]` are set.
+    Tcr,
+}
+
+/// One register access as decoded by [`MockSpi`], see [`MockSpi::log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    /// Whether the RAB had its read bit set.
+    pub read: bool,
+    /// The channel selected by the RAB.
+    pub channel: Channel,
+    /// The window LCR selected when the access started.
+    pub window: Window,
+    /// The register address from the RAB, 0x00 to 0x0F.
+    pub reg: u8,
+    /// The bytes written, or read back, in order.
+    pub data: Vec<u8>,
+}
+
+type Key = (u8, Window, u8);
+
+#[derive(Default)]
+struct State {
+    values: BTreeMap<Key, u8>,
+    queued: BTreeMap<Key, VecDeque<u8>>,
+    log: Vec<Access>,
+    transactions: usize,
+    /// Number of garbage bytes the device clocks out before read data.
+    turnaround: usize,
+    /// Turnaround bytes still to go in the current transaction.
+    pending_turnaround: usize,
+    /// Bytes still expected by a frame cut off earlier, which the device
+    /// swallows instead of decoding a RAB.
+    partial_frame: usize,
+}
+
+impl State {
+    fn value(&self, channel: Channel, window: Window, reg: u8) -> u8 {
+        self.values
+            .get(&(channel as u8, window, reg))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn window(&self, channel: Channel, reg: u8) -> Window {
+        let lcr = self.value(channel, Window::General, LCR);
+
+        if reg == LCR || reg >= 0x08 {
+            Window::General
+        } else if lcr == 0xBF && reg != 0 && reg != 1 {
+            Window::Enhanced
+        } else if lcr & 0x80 != 0 && (reg == 0 || reg == 1) {
+            Window::Latch
+        } else if (reg == 0x06 || reg == 0x07)
+            && self.value(channel, Window::General, MCR) & 0x04 != 0
+            && self.value(channel, Window::Enhanced, 0x02) & 0x10 != 0
+        {
+            Window::Tcr
+        } else {
+            Window::General
+        }
+    }
+
+    /// Clocks one byte through the device, returning the byte on MISO.
+    fn clock(&mut self, access: &mut Option<Access>, mosi: u8) -> u8 {
+        let Some(access) = access else {
+            if self.partial_frame > 0 {
+                self.partial_frame -= 1;
+                return 0xFF;
+            }
+
+            let channel = match (mosi >> 1) & 0b11 {
+                0b00 => Channel::A,
+                _ => Channel::B,
+            };
+            let reg = (mosi >> 3) & 0x0F;
+            *access = Some(Access {
+                read: mosi & 0x80 != 0,
+                channel,
+                window: self.window(channel, reg),
+                reg,
+                data: Vec::new(),
+            });
+            self.pending_turnaround = self.turnaround;
+            return 0;
+        };
+
+        if access.read && self.pending_turnaround > 0 {
+            self.pending_turnaround -= 1;
+            return 0xFF;
+        }
+
+        let key = (access.channel as u8, access.window, access.reg);
+        let rhr = (access.channel as u8, Window::General, RHR);
+        let loopback = access.window == Window::General
+            && self.value(access.channel, Window::General, MCR) & 0x10 != 0;
+        let byte = if access.read && loopback && access.reg == RXLVL {
+            self.queued.get(&rhr).map_or(0, VecDeque::len) as u8
+        } else if access.read {
+            self.queued
+                .get_mut(&key)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or_else(|| self.value(access.channel, access.window, access.reg))
+        } else {
+            if loopback && access.reg == THR {
+                self.queued.entry(rhr).or_default().push_back(mosi);
+            }
+            // THR/FCR share their addresses with RHR/IIR, so writing them must
+            // not change what reads back.
+            if access.window != Window::General || (access.reg != 0 && access.reg != 2) {
+                self.values.insert(key, mosi);
+            }
+            mosi
+        };
+        access.data.push(byte);
+
+        if access.read { byte } else { 0 }
+    }
+}
+
+/// A fake [`SpiDevice`] backed by a register model. Clones share state, so a
+/// test can keep one handle for inspection after giving another to the driver.
+#[derive(Clone, Default)]
+pub struct MockSpi {
+    state: Rc<RefCell<State>>,
+}
+
+impl MockSpi {
+    /// Creates a bus with every register reading 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value a register reads back once its queue is exhausted.
+    pub fn set(&self, channel: Channel, window: Window, reg: u8, value: u8) {
+        self.state
+            .borrow_mut()
+            .values
+            .insert((channel as u8, window, reg), value);
+    }
+
+    /// Makes reads clock out `bytes` garbage bytes between the RAB and the
+    /// data, like a device that needs extra turnaround.
+    pub fn set_turnaround(&self, bytes: usize) {
+        self.state.borrow_mut().turnaround = bytes;
+    }
+
+    /// Simulates a frame cut off with `bytes` still to go: the device treats
+    /// that many of the following bytes as belonging to it.
+    pub fn set_partial_frame(&self, bytes: usize) {
+        self.state.borrow_mut().partial_frame = bytes;
+    }
+
+    /// Queues one-shot values returned by the next reads of a register.
+    pub fn queue(&self, channel: Channel, window: Window, reg: u8, values: &[u8]) {
+        self.state
+            .borrow_mut()
+            .queued
+            .entry((channel as u8, window, reg))
+            .or_default()
+            .extend(values);
+    }
+
+    /// The value a register reads back once its queue is exhausted: the
+    /// last one written or [set](Self::set), 0 by default.
+    pub fn get(&self, channel: Channel, window: Window, reg: u8) -> u8 {
+        self.state.borrow().value(channel, window, reg)
+    }
+
+    /// Every byte written to a register, in order.
+    pub fn writes(&self, channel: Channel, window: Window, reg: u8) -> Vec<u8> {
+        self.state
+            .borrow()
+            .log
+            .iter()
+            .filter(|a| !a.read && a.channel == channel && a.window == window && a.reg == reg)
+            .flat_map(|a| a.data.iter().copied())
+            .collect()
+    }
+
+    /// Every register write as `(window, register, value)`, in order.
+    pub fn write_sequence(&self) -> Vec<(Window, u8, u8)> {
+        self.state
+            .borrow()
+            .log
+            .iter()
+            .filter(|a| !a.read)
+            .flat_map(|a| a.data.iter().map(|&byte| (a.window, a.reg, byte)))
+            .collect()
+    }
+
+    /// Every access so far, reads included, in order.
+    pub fn log(&self) -> Vec<Access> {
+        self.state.borrow().log.clone()
+    }
+
+    /// The number of SPI transactions so far.
+    pub fn transactions(&self) -> usize {
+        self.state.borrow().transactions
+    }
+}
+
+impl ErrorType for MockSpi {
+    type Error = Infallible;
+}
+
+impl embedded_hal::spi::SpiDevice for MockSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        block_on(SpiDevice::transaction(self, operations))
+    }
+}
+
+impl SpiDevice for MockSpi {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        let mut state = self.state.borrow_mut();
+        let mut access = None;
+        state.transactions += 1;
+        // A driver loop that never yields would otherwise hang the test
+        assert!(state.transactions < 100_000, "runaway SPI traffic");
+
+        for op in operations {
+            match op {
+                Operation::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = state.clock(&mut access, 0);
+                    }
+                }
+                Operation::Write(buf) => {
+                    for &byte in buf.iter() {
+                        state.clock(&mut access, byte);
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    for i in 0..read.len().max(write.len()) {
+                        let miso = state.clock(&mut access, write.get(i).copied().unwrap_or(0));
+                        if let Some(byte) = read.get_mut(i) {
+                            *byte = miso;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = state.clock(&mut access, *byte);
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+
+        state.log.extend(access);
+        Ok(())
+    }
+}
+
+/// A fake I2C bus with the chip at `address`, backed by the same register
+/// model as [`MockSpi`]: each transfer is replayed as the SPI transaction
+/// with the same register address byte.
+pub struct MockI2c {
+    /// The register model, which can be inspected through a clone.
+    pub spi: MockSpi,
+    /// The 7-bit address the chip answers on; others fail the test.
+    pub address: u8,
+}
+
+impl i2c::ErrorType for MockI2c {
+    type Error = Infallible;
+}
+
+impl I2c for MockI2c {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Infallible> {
+        assert_eq!(address, self.address, "no device at {address:#04x}");
+
+        let (subaddress, data) = match operations {
+            [i2c::Operation::Write(subaddress), data @ ..] => (subaddress[0], data),
+            _ => panic!("transfer does not start with a subaddress"),
+        };
+        let read = matches!(data, [i2c::Operation::Read(_)]);
+        let rab = if read { subaddress | 0x80 } else { subaddress };
+
+        let mut ops: Vec<Operation<'_, u8>> =
+            std::vec![Operation::Write(core::slice::from_ref(&rab))];
+        for op in data {
+            match op {
+                i2c::Operation::Read(buf) => ops.push(Operation::Read(buf)),
+                i2c::Operation::Write(buf) => ops.push(Operation::Write(buf)),
+            }
+        }
+        self.spi.transaction(&mut ops).await
+    }
+}
+
+/// An IRQ pin that is always asserted.
+pub struct MockIrq;
+
+impl embedded_hal::digital::ErrorType for MockIrq {
+    type Error = Infallible;
+}
+
+impl Wait for MockIrq {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// An IRQ pin that never changes level, as if it were not connected.
+pub struct StuckIrq;
+
+impl embedded_hal::digital::ErrorType for StuckIrq {
+    type Error = Infallible;
+}
+
+impl Wait for StuckIrq {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+}
+
+/// An IRQ pin whose GPIO driver fails every wait.
+pub struct FaultyIrq;
+
+impl embedded_hal::digital::ErrorType for FaultyIrq {
+    type Error = embedded_hal::digital::ErrorKind;
+}
+
+impl Wait for FaultyIrq {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+}
+
+/// An IRQ pin whose level the test sets. Waiting for low stays pending
+/// while it is high; edges are not modeled. Clones share the level, like
+/// two handles on one pin.
+#[derive(Clone, Default)]
+pub struct MockPin {
+    low: Rc<Cell<bool>>,
+}
+
+impl MockPin {
+    /// Drives the pin low (asserted) or high, for every clone.
+    pub fn set_low(&self, low: bool) {
+        self.low.set(low);
+    }
+
+    async fn wait_for(&self, low: bool) {
+        poll_fn(|_| {
+            if self.low.get() == low {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl embedded_hal::digital::ErrorType for MockPin {
+    type Error = Infallible;
+}
+
+impl embedded_hal::digital::InputPin for MockPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.low.get())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(self.low.get())
+    }
+}
+
+impl Wait for MockPin {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for_high().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for_low().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        core::future::pending().await
+    }
+}
+
+/// A delay that returns immediately, adding up how long it was asked to wait.
+#[derive(Default)]
+pub struct MockDelay {
+    /// The sum of every delay asked for.
+    pub total_ns: u64,
+    /// The number of delays asked for.
+    pub calls: usize,
+}
+
+impl DelayNs for MockDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.total_ns += u64::from(ns);
+        self.calls += 1;
+    }
+}
+
+/// Drives a future to completion. The mocks never block, so a future that is
+/// still pending after many polls is stuck and fails the test.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    for _ in 0..10_000 {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+
+    panic!("future did not complete");
+}
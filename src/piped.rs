@@ -0,0 +1,227 @@
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::pin::pin;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::pipe::Pipe;
+use embedded_hal_async::digital::Wait;
+use heapless::Vec;
+
+use crate::interface::RegisterInterface;
+use crate::low_level::Ier;
+use crate::{Error, InterruptSource, Sc16is752};
+
+/// A channel whose FIFOs are connected to an RX and a TX [`Pipe`] by
+/// [`Self::run`], like a [`BufferedSc16is752`](crate::BufferedSc16is752)
+/// but with pipes that can be shared between tasks and executors: tasks
+/// read the received bytes from `rx` and write bytes to send to `tx`.
+///
+/// ```ignore
+/// static RX: Pipe<CriticalSectionRawMutex, 256> = Pipe::new();
+/// static TX: Pipe<CriticalSectionRawMutex, 256> = Pipe::new();
+///
+/// let mut piped = PipedSc16is752::new(uart, &RX, &TX);
+/// spawner.spawn(uart_task(piped))?; // loops on piped.run()
+/// TX.write_all(b"AT\r").await;
+/// ```
+pub struct PipedSc16is752<
+    'a,
+    M: RawMutex,
+    Spi,
+    Irq,
+    const RX: usize,
+    const TX: usize,
+    const BUF: usize = 64,
+> {
+    uart: Sc16is752<Spi, Irq, BUF>,
+    rx: &'a Pipe<M, RX>,
+    tx: &'a Pipe<M, TX>,
+    /// Read from the RX FIFO, waiting for room in `rx`.
+    rx_held: Vec<u8, 64>,
+    /// Taken from `tx`, waiting for room in the TX FIFO.
+    tx_held: Vec<u8, 64>,
+    /// The last value written to IER.
+    ier: Option<u8>,
+}
+
+/// What ended the wait in [`PipedSc16is752::run`].
+enum Woken<E> {
+    Irq(Result<(), E>),
+    TxData(usize),
+    RxSpace(usize),
+}
+
+impl<'a, M, Spi, Irq, const RX: usize, const TX: usize, const BUF: usize>
+    PipedSc16is752<'a, M, Spi, Irq, RX, TX, BUF>
+where
+    M: RawMutex,
+    Spi: RegisterInterface,
+    Irq: Wait,
+{
+    /// Takes over an initialized channel.
+    pub fn new(uart: Sc16is752<Spi, Irq, BUF>, rx: &'a Pipe<M, RX>, tx: &'a Pipe<M, TX>) -> Self {
+        PipedSc16is752 {
+            uart,
+            rx,
+            tx,
+            rx_held: Vec::new(),
+            tx_held: Vec::new(),
+            ier: None,
+        }
+    }
+
+    /// Moves data between the FIFOs and the pipes until an error occurs,
+    /// waiting on the IRQ pin, on data in `tx` and on room in `rx` in
+    /// between. Call it again to carry on after an error; nothing is lost.
+    ///
+    /// While `rx` is full, data is left in the RX FIFO and the RX interrupt
+    /// is disabled, so that flow control can halt the peer.
+    pub async fn run(&mut self) -> Result<Infallible, Error<Spi::Error>> {
+        loop {
+            self.service().await?;
+
+            let PipedSc16is752 {
+                uart,
+                rx,
+                tx,
+                rx_held,
+                tx_held,
+                ..
+            } = self;
+            let mut chunk = [0; 64];
+            let wait_tx = tx_held.is_empty();
+            let wait_rx = !rx_held.is_empty();
+
+            let woken = {
+                let mut irq = pin!(uart.irq.wait_for_low());
+                let mut tx_data = pin!(tx.read(&mut chunk));
+                let mut rx_space = pin!(rx.write(rx_held));
+                poll_fn(|cx| {
+                    if let Poll::Ready(result) = irq.as_mut().poll(cx) {
+                        return Poll::Ready(Woken::Irq(result));
+                    }
+                    if wait_tx && let Poll::Ready(len) = tx_data.as_mut().poll(cx) {
+                        return Poll::Ready(Woken::TxData(len));
+                    }
+                    if wait_rx && let Poll::Ready(len) = rx_space.as_mut().poll(cx) {
+                        return Poll::Ready(Woken::RxSpace(len));
+                    }
+                    Poll::Pending
+                })
+                .await
+            };
+
+            match woken {
+                Woken::Irq(result) => result.map_err(Error::irq)?,
+                Woken::TxData(len) => {
+                    // Fits, as tx_held was empty
+                    tx_held.extend_from_slice(&chunk[..len]).ok();
+                }
+                Woken::RxSpace(len) => {
+                    rx_held.drain(..len);
+                }
+            }
+        }
+    }
+
+    /// Gives the channel back, along with bytes it had taken from the RX
+    /// FIFO or from `tx` but not yet passed on.
+    pub fn release(self) -> (Sc16is752<Spi, Irq, BUF>, Vec<u8, 64>, Vec<u8, 64>) {
+        (self.uart, self.rx_held, self.tx_held)
+    }
+
+    /// Clears pending interrupts, moves data as far as the pipes and FIFOs
+    /// allow and enables the interrupts needed for what is left to do.
+    async fn service(&mut self) -> Result<(), Error<Spi::Error>> {
+        // A line status interrupt is only cleared by reading LSR
+        if self.uart.poll_interrupt().await? == Some(InterruptSource::ReceiveLineStatusError) {
+            self.uart.read_lsr().await?;
+        }
+
+        if self.rx_held.is_empty() {
+            let mut chunk = [0; 64];
+            let len = self.uart.read_fifo(&mut chunk).await?;
+            // Fits, as rx_held was empty
+            self.rx_held.extend_from_slice(&chunk[..len]).ok();
+        }
+        if !self.rx_held.is_empty() {
+            let len = self.rx.try_write(&self.rx_held).unwrap_or(0);
+            self.rx_held.drain(..len);
+        }
+
+        if self.tx_held.is_empty() {
+            let mut chunk = [0; 64];
+            let len = self.tx.try_read(&mut chunk).unwrap_or(0);
+            self.tx_held.extend_from_slice(&chunk[..len]).ok();
+        }
+        if !self.tx_held.is_empty() {
+            let len = self.tx_held.len().min(self.uart.tx_space().await?);
+            if len > 0 {
+                self.uart
+                    .regs
+                    .write_many_thr(self.uart.channel, &self.tx_held[..len])
+                    .await?;
+                self.tx_held.drain(..len);
+            }
+        }
+
+        let ier = Ier::new()
+            .with_receive_holding_register(self.rx_held.is_empty())
+            .with_transmit_holding_register(!self.tx_held.is_empty());
+        let [bits] = ier.into_bytes();
+        if self.ier != Some(bits) {
+            self.uart.regs.write_ier(self.uart.channel, ier).await?;
+            self.ier = Some(bits);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::task::{Context, Waker};
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::Channel;
+    use crate::low_level::{IER, RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockPin, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_piped() {
+        let spi = MockSpi::new();
+        let irq = MockPin::default();
+        let mut uart = Sc16is752::new(spi.clone(), irq.clone(), Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        let rx = Pipe::<NoopRawMutex, 4>::new();
+        let tx = Pipe::<NoopRawMutex, 8>::new();
+        let mut piped = PipedSc16is752::new(uart, &rx, &tx);
+
+        spi.set(Channel::A, Window::General, RXLVL, 6);
+        spi.queue(Channel::A, Window::General, RHR, b"abcdef");
+        spi.set(Channel::A, Window::General, TXLVL, 64);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut run = pin!(piped.run());
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+
+        // The pipe took four bytes, the other two wait for room
+        let mut buf = [0; 8];
+        assert_eq!(rx.try_read(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"abcd");
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x00);
+        spi.set(Channel::A, Window::General, RXLVL, 0);
+
+        block_on(tx.write_all(b"hello"));
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"hello");
+        assert_eq!(rx.try_read(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"ef");
+        assert!(run.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(spi.get(Channel::A, Window::General, IER), 0x01);
+    }
+}
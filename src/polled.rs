@@ -0,0 +1,94 @@
+use core::convert::Infallible;
+
+use embedded_hal::digital::ErrorType;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+
+/// Stands in for the IRQ pin on boards that don't route it: every wait
+/// just sleeps for the poll interval, after which the driver checks the
+/// FIFO levels again.
+///
+/// ```ignore
+/// let irq = NoIrq::new(embassy_time::Delay, 500);
+/// let mut uart = Sc16is752::new(spi, irq, Channel::A);
+/// ```
+///
+/// Shorter intervals cost SPI traffic, longer ones latency: at 115200 baud
+/// the 64-byte RX FIFO fills in about 5.5 ms.
+pub struct NoIrq<D> {
+    delay: D,
+    interval_us: u32,
+}
+
+impl<D: DelayNs> NoIrq<D> {
+    pub fn new(delay: D, interval_us: u32) -> Self {
+        NoIrq { delay, interval_us }
+    }
+
+    pub fn release(self) -> D {
+        self.delay
+    }
+
+    async fn poll_interval(&mut self) -> Result<(), Infallible> {
+        self.delay.delay_us(self.interval_us).await;
+        Ok(())
+    }
+}
+
+impl<D> ErrorType for NoIrq<D> {
+    type Error = Infallible;
+}
+
+impl<D: DelayNs> Wait for NoIrq<D> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.poll_interval().await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.poll_interval().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.poll_interval().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.poll_interval().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.poll_interval().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io_async::{Read, Write};
+
+    use super::*;
+    use crate::low_level::{RHR, RXLVL, THR, TXLVL};
+    use crate::mock::{MockDelay, MockSpi, Window, block_on};
+    use crate::{Channel, Sc16is752};
+
+    #[test]
+    fn test_no_irq() {
+        let spi = MockSpi::new();
+        let irq = NoIrq::new(MockDelay::default(), 250);
+        let mut uart = Sc16is752::new(spi.clone(), irq, Channel::A);
+        block_on(uart.init(9600, 1_843_200)).unwrap();
+
+        // Data shows up after two polls
+        spi.queue(Channel::A, Window::General, RXLVL, &[0, 0, 3]);
+        spi.queue(Channel::A, Window::General, RHR, b"abc");
+        let mut buf = [0; 8];
+        assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+
+        spi.queue(Channel::A, Window::General, TXLVL, &[0, 64]);
+        assert_eq!(block_on(uart.write(b"xyz")).unwrap(), 3);
+        assert_eq!(spi.writes(Channel::A, Window::General, THR), b"xyz");
+
+        assert_eq!(uart.irq.delay.calls, 3);
+        assert_eq!(uart.irq.delay.total_ns, 3 * 250_000);
+    }
+}
@@ -0,0 +1,212 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embedded_hal::digital::ErrorType;
+use embedded_hal_async::digital::Wait;
+use heapless::Vec;
+
+use crate::yield_now;
+
+/// Shares one IRQ pin between several drivers, for chips whose IRQ outputs
+/// are wire-OR'd (open-drain) onto a single MCU pin.
+///
+/// Each driver gets a [`SharedIrq`] from [`Self::handle`] in place of the
+/// pin. Of the handles waiting at a time, one awaits the pin itself and the
+/// others are woken when it returns, so every driver checks its own IIR and
+/// those with nothing pending go back to waiting. Every wait first yields
+/// to the executor, as the line stays asserted until all chips have been
+/// serviced. As with a
+/// [`BufferedState`](crate::BufferedState), the handles have to run on the
+/// same executor.
+///
+/// Up to `N` waiters are tracked; any beyond that are woken right away
+/// instead, and simply poll.
+///
+/// ```ignore
+/// let irq = IrqDispatcher::<_, 4>::new(pin);
+/// let mut modem = Sc16is752::new(spi_a, irq.handle(), Channel::A);
+/// let mut gnss = Sc16is752::new(spi_b, irq.handle(), Channel::A);
+/// ```
+pub struct IrqDispatcher<Irq, const N: usize = 4> {
+    irq: RefCell<Irq>,
+    /// Bumped each time a wait on the pin completes.
+    generation: Cell<u32>,
+    waiters: RefCell<Vec<Waker, N>>,
+}
+
+impl<Irq: Wait, const N: usize> IrqDispatcher<Irq, N> {
+    /// Takes over the pin shared by the chips' IRQ outputs.
+    pub const fn new(irq: Irq) -> Self {
+        IrqDispatcher {
+            irq: RefCell::new(irq),
+            generation: Cell::new(0),
+            waiters: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Creates a handle on the pin to pass to one driver in its place.
+    pub fn handle(&self) -> SharedIrq<'_, Irq, N> {
+        SharedIrq { dispatcher: self }
+    }
+
+    /// Gives the pin back, which needs every handle to have been dropped.
+    pub fn release(self) -> Irq {
+        self.irq.into_inner()
+    }
+
+    fn wake_all(&self) {
+        for waker in self.waiters.borrow_mut().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Wakes the other waiters when the handle awaiting the pin is done with it,
+/// also when its future is dropped, so that one of them takes over.
+struct Leader<'a, Irq: Wait, const N: usize>(&'a IrqDispatcher<Irq, N>);
+
+impl<Irq: Wait, const N: usize> Drop for Leader<'_, Irq, N> {
+    fn drop(&mut self) {
+        self.0.wake_all();
+    }
+}
+
+/// A handle on the pin of an [`IrqDispatcher`], implementing [`Wait`].
+///
+/// A wait returns when the pin's does, for whichever handle awaited it; only
+/// that one reports an error from the pin, the others just wake up.
+pub struct SharedIrq<'a, Irq, const N: usize = 4> {
+    dispatcher: &'a IrqDispatcher<Irq, N>,
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    High,
+    Low,
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
+}
+
+impl<Irq: Wait, const N: usize> SharedIrq<'_, Irq, N> {
+    #[allow(
+        clippy::await_holding_refcell_ref,
+        reason = "the borrow marks the handle awaiting the pin, the others only try_borrow"
+    )]
+    async fn wait(&mut self, condition: Condition) -> Result<(), Irq::Error> {
+        // Otherwise a driver that found nothing to do would spin on the
+        // asserted line without the others ever getting to service it
+        yield_now().await;
+
+        let dispatcher = self.dispatcher;
+        let start = dispatcher.generation.get();
+
+        loop {
+            if let Ok(mut irq) = dispatcher.irq.try_borrow_mut() {
+                let _leader = Leader(dispatcher);
+                let result = match condition {
+                    Condition::High => irq.wait_for_high().await,
+                    Condition::Low => irq.wait_for_low().await,
+                    Condition::RisingEdge => irq.wait_for_rising_edge().await,
+                    Condition::FallingEdge => irq.wait_for_falling_edge().await,
+                    Condition::AnyEdge => irq.wait_for_any_edge().await,
+                };
+                dispatcher
+                    .generation
+                    .set(dispatcher.generation.get().wrapping_add(1));
+                return result;
+            }
+
+            // Another handle is awaiting the pin
+            poll_fn(|cx| {
+                if dispatcher.generation.get() != start || dispatcher.irq.try_borrow_mut().is_ok() {
+                    return Poll::Ready(());
+                }
+                let mut waiters = dispatcher.waiters.borrow_mut();
+                if !waiters.iter().any(|waker| waker.will_wake(cx.waker()))
+                    && waiters.push(cx.waker().clone()).is_err()
+                {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            })
+            .await;
+
+            if dispatcher.generation.get() != start {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<Irq: Wait, const N: usize> ErrorType for SharedIrq<'_, Irq, N> {
+    type Error = Irq::Error;
+}
+
+impl<Irq: Wait, const N: usize> Wait for SharedIrq<'_, Irq, N> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait(Condition::High).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait(Condition::Low).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Condition::RisingEdge).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Condition::FallingEdge).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(Condition::AnyEdge).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use embedded_io_async::Read;
+
+    use super::*;
+    use crate::Channel;
+    use crate::Sc16is752;
+    use crate::low_level::{IIR, RHR, RXLVL};
+    use crate::mock::{MockPin, MockSpi, Window, block_on};
+
+    #[test]
+    fn test_shared_irq() {
+        let pin = MockPin::default();
+        let dispatcher = IrqDispatcher::<_, 2>::new(pin.clone());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let (spi_a, spi_b) = (MockSpi::new(), MockSpi::new());
+        let mut first = Sc16is752::new(spi_a.clone(), dispatcher.handle(), Channel::A);
+        let mut second = Sc16is752::new(spi_b.clone(), dispatcher.handle(), Channel::B);
+        block_on(first.set_fifo_enabled(true)).unwrap();
+        block_on(second.set_fifo_enabled(true)).unwrap();
+
+        let (mut buf_a, mut buf_b) = ([0; 4], [0; 4]);
+        let mut read_a = pin!(first.read(&mut buf_a));
+        let mut read_b = pin!(second.read(&mut buf_b));
+        assert!(read_a.as_mut().poll(&mut cx).is_pending());
+        assert!(read_b.as_mut().poll(&mut cx).is_pending());
+
+        // The second chip asserts the shared line
+        spi_b.set(Channel::B, Window::General, IIR, 0xC4);
+        spi_b.set(Channel::B, Window::General, RXLVL, 2);
+        spi_b.queue(Channel::B, Window::General, RHR, b"hi");
+        pin.set_low(true);
+
+        assert!(read_a.as_mut().poll(&mut cx).is_pending());
+        assert!(matches!(read_b.as_mut().poll(&mut cx), Poll::Ready(Ok(2))));
+        // The first chip had nothing pending and went back to waiting
+        assert!(read_a.as_mut().poll(&mut cx).is_pending());
+        assert!(spi_a.transactions() > 2);
+    }
+}
@@ -0,0 +1,70 @@
+//! End-to-end tests of the public API against the register model behind the
+//! `mock` feature.
+
+use embedded_io_async::{Read, Write};
+use sc16is752_async::mock::{MockIrq, MockPin, MockSpi, Window, block_on};
+use sc16is752_async::registers::{DLH, DLL, IER, IIR, LCR, LSR, MCR, RHR, RXLVL, THR, TXLVL};
+use sc16is752_async::{Channel, Sc16is752};
+
+#[test]
+fn init_programs_the_line() {
+    let spi = MockSpi::new();
+    let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::B);
+
+    block_on(uart.init(115_200, 14_745_600)).unwrap();
+    assert_eq!(uart.current_config().0, 115_200);
+    assert_eq!(spi.get(Channel::B, Window::Latch, DLL), 8);
+    assert_eq!(spi.get(Channel::B, Window::Latch, DLH), 0);
+    assert_eq!(spi.get(Channel::B, Window::General, LCR), 0x03);
+    assert_eq!(spi.get(Channel::B, Window::General, IER), 0x01);
+    assert!(spi.log().iter().all(|access| access.channel == Channel::B));
+}
+
+#[test]
+fn loopback_round_trip() {
+    let spi = MockSpi::new();
+    let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+    block_on(uart.init(9600, 1_843_200)).unwrap();
+    spi.set(Channel::A, Window::General, TXLVL, 64);
+
+    block_on(uart.set_loopback(true)).unwrap();
+    assert_eq!(spi.get(Channel::A, Window::General, MCR) & 0x10, 0x10);
+
+    block_on(uart.write_all(b"hello, host")).unwrap();
+    let mut buf = [0; 16];
+    let len = block_on(uart.read(&mut buf)).unwrap();
+    assert_eq!(&buf[..len], b"hello, host");
+}
+
+#[test]
+fn read_waits_for_the_rx_interrupt() {
+    let spi = MockSpi::new();
+    let irq = MockPin::default();
+    let mut uart = Sc16is752::new(spi.clone(), irq.clone(), Channel::A);
+    block_on(uart.init(9600, 1_843_200)).unwrap();
+
+    // Nothing yet, then the IRQ line drops with data in the FIFO
+    spi.queue(Channel::A, Window::General, RXLVL, &[0, 3]);
+    spi.queue(Channel::A, Window::General, RHR, b"abc");
+    spi.set(Channel::A, Window::General, IIR, 0xC4);
+    irq.set_low(true);
+
+    let mut buf = [0; 8];
+    assert_eq!(block_on(uart.read(&mut buf)).unwrap(), 3);
+    assert_eq!(&buf[..3], b"abc");
+}
+
+#[test]
+fn write_and_flush() {
+    let spi = MockSpi::new();
+    let mut uart = Sc16is752::new(spi.clone(), MockIrq, Channel::A);
+    block_on(uart.init(9600, 1_843_200)).unwrap();
+
+    // The FIFO has room for four bytes, then for the rest
+    spi.queue(Channel::A, Window::General, TXLVL, &[4, 0, 64]);
+    spi.set(Channel::A, Window::General, TXLVL, 64);
+    spi.set(Channel::A, Window::General, LSR, 0x60);
+    block_on(uart.write_all(b"0123456789")).unwrap();
+    block_on(uart.flush()).unwrap();
+    assert_eq!(spi.writes(Channel::A, Window::General, THR), b"0123456789");
+}